@@ -1,5 +1,12 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
+use crate::debugreg::Trigger;
+use crate::dwarf::types::TypeInfo;
+use crate::expr::Expr;
 use crate::prelude::*;
 use crate::ptrace;
+use crate::syscall::syscall_name;
 
 #[derive(Debug, Clone, Hash)]
 pub struct Breakpoint {
@@ -63,6 +70,21 @@ impl Breakpoint {
         Ok(())
     }
 
+    /// Puts the original byte back in `pid`, a forked copy of the process that
+    /// inherited this `INT3`, leaving our own copy alone.
+    pub fn remove_from(&self, pid: Pid) -> Result<(), ptrace::Error> {
+        let old_byte = self.replacing_byte.expect("Was enabled");
+        let mut bytes = ptrace::peekdata(pid, self.addr)?.to_ne_bytes();
+        bytes[0] = old_byte;
+        ptrace::pokedata(pid, self.addr, i64::from_ne_bytes(bytes))
+    }
+
+    /// Makes the breakpoint patch process `pid` from now on, e.g. a fork child
+    /// we followed that inherited it.
+    pub fn set_pid(&mut self, pid: Pid) {
+        self.pid = pid;
+    }
+
     pub fn disable(&mut self) -> Result<(), ptrace::Error> {
         assert!(self.enabled);
         let data = ptrace::peekdata(self.pid, self.addr)?;
@@ -79,3 +101,183 @@ impl Breakpoint {
         Ok(())
     }
 }
+
+/// Where a user asked for a breakpoint to be placed, kept so it can be shown back
+/// to them and resolved again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Location {
+    Address(u64),
+    Function(String),
+    Line { file: Option<String>, line: u64 },
+    /// An event a catchpoint stops for, rather than a place in the code.
+    Event(CatchEvent),
+}
+
+/// What a catchpoint stops for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CatchEvent {
+    /// The process calling `exec` to run a new program.
+    Exec,
+    /// Entering or leaving one of these system calls, or any when empty.
+    Syscall(Vec<u64>),
+}
+
+impl CatchEvent {
+    /// Whether this catches system call `number`.
+    pub fn catches_syscall(&self, number: u64) -> bool {
+        match self {
+            CatchEvent::Syscall(numbers) => numbers.is_empty() || numbers.contains(&number),
+            CatchEvent::Exec => false,
+        }
+    }
+}
+
+impl Display for CatchEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CatchEvent::Exec => write!(f, "exec"),
+            CatchEvent::Syscall(numbers) if numbers.is_empty() => write!(f, "syscall"),
+            CatchEvent::Syscall(numbers) => {
+                let names: Vec<String> = numbers.iter().map(|&number| syscall_name(number)).collect();
+                write!(f, "syscall {}", names.join(", "))
+            }
+        }
+    }
+}
+
+impl FromStr for Location {
+    type Err = &'static str;
+
+    /// Parses `*ADDR`, `0xADDR`, `LINE`, `FILE:LINE` or `FUNCTION`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid_address = |_| "Invalid address";
+        if let Some(addr) = s.strip_prefix('*') {
+            return parse_address(addr).map(Self::Address).map_err(invalid_address);
+        }
+        if s.starts_with("0x") {
+            return parse_address(s).map(Self::Address).map_err(invalid_address);
+        }
+        if let Ok(line) = s.parse() {
+            return Ok(Self::Line { file: None, line });
+        }
+        if let Some((file, line)) = s.rsplit_once(':') {
+            let line = line.parse().map_err(|_| "Invalid line number")?;
+            return Ok(Self::Line {
+                file: Some(file.to_string()),
+                line,
+            });
+        }
+        Ok(Self::Function(s.to_string()))
+    }
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Location::Address(addr) => write!(f, "*0x{addr:x}"),
+            Location::Function(function) => write!(f, "{function}"),
+            Location::Line { file: Some(file), line } => write!(f, "{file}:{line}"),
+            Location::Line { file: None, line } => write!(f, "{line}"),
+            Location::Event(event) => write!(f, "{event}"),
+        }
+    }
+}
+
+/// What kind of access a watchpoint reports.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WatchAccess {
+    /// `watch`: the value changed.
+    Write,
+    /// `rwatch`: the value was read. x86 can't trap on reads alone, so these are
+    /// accesses that left the value unchanged.
+    Read,
+    /// `awatch`: any read or write.
+    Access,
+}
+
+#[derive(Debug, Clone)]
+pub enum BreakpointKind {
+    /// Patches an `INT3` over the instruction.
+    Software,
+    /// Uses a debug register to trap on execution, leaving memory untouched.
+    Hardware,
+    /// Uses a debug register to trap on accesses to `ty.size` bytes of memory.
+    Watch {
+        access: WatchAccess,
+        ty: TypeInfo,
+        old_value: Vec<u8>,
+    },
+    /// Stops for the event in its [`Location::Event`] instead of at an address.
+    Catchpoint,
+}
+
+impl BreakpointKind {
+    /// How a debug register has to be programmed for this kind, and over how many
+    /// bytes. `None` for kinds that don't use one.
+    pub fn debug_register_trigger(&self) -> Option<(Trigger, u64)> {
+        match self {
+            BreakpointKind::Software | BreakpointKind::Catchpoint => None,
+            BreakpointKind::Hardware => Some((Trigger::Execute, 1)),
+            BreakpointKind::Watch { access, ty, .. } => {
+                let trigger = match access {
+                    WatchAccess::Write => Trigger::Write,
+                    WatchAccess::Read | WatchAccess::Access => Trigger::ReadWrite,
+                };
+                Some((trigger, ty.size))
+            }
+        }
+    }
+}
+
+impl Display for BreakpointKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BreakpointKind::Software => write!(f, "breakpoint"),
+            BreakpointKind::Hardware => write!(f, "hw breakpoint"),
+            BreakpointKind::Watch { access: WatchAccess::Write, .. } => write!(f, "hw watchpoint"),
+            BreakpointKind::Watch { access: WatchAccess::Read, .. } => write!(f, "read watchpoint"),
+            BreakpointKind::Watch { access: WatchAccess::Access, .. } => write!(f, "acc watchpoint"),
+            BreakpointKind::Catchpoint => write!(f, "catchpoint"),
+        }
+    }
+}
+
+/// A numbered breakpoint as the user sees it. A single one can own several
+/// addresses, e.g. every inlined copy of a function.
+#[derive(Debug, Clone)]
+pub struct UserBreakpoint {
+    pub number: usize,
+    pub location: Location,
+    pub kind: BreakpointKind,
+    pub addresses: Vec<u64>,
+    pub enabled: bool,
+    pub hit_count: u64,
+    /// Only stop when this evaluates to non-zero.
+    pub condition: Option<Expr>,
+}
+
+impl UserBreakpoint {
+    pub fn new(number: usize, location: Location, kind: BreakpointKind, addresses: Vec<u64>) -> Self {
+        Self {
+            number,
+            location,
+            kind,
+            addresses,
+            enabled: true,
+            hit_count: 0,
+            condition: None,
+        }
+    }
+
+    /// Whether this breakpoint needs an `INT3` patched in at `addr`.
+    pub fn wants(&self, addr: u64) -> bool {
+        self.enabled
+            && matches!(self.kind, BreakpointKind::Software)
+            && self.addresses.contains(&addr)
+    }
+
+    /// Whether this breakpoint needs a debug register.
+    pub fn wants_debug_register(&self) -> bool {
+        self.enabled && self.kind.debug_register_trigger().is_some()
+    }
+}
@@ -0,0 +1,154 @@
+//! The x86 debug registers: DR0-DR3 hold addresses to watch, DR6 reports which of
+//! them triggered, and DR7 controls what kind of access each one traps on.
+
+use crate::prelude::*;
+use crate::ptrace;
+
+pub const NUM_SLOTS: usize = 4;
+
+const DR6_INDEX: usize = 6;
+const DR7_INDEX: usize = 7;
+
+/// The R/W bits in DR7 for a slot.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Trigger {
+    Execute = 0b00,
+    Write = 0b01,
+    ReadWrite = 0b11,
+}
+
+fn debugreg_offset(index: usize) -> usize {
+    std::mem::offset_of!(libc::user, u_debugreg) + index * std::mem::size_of::<u64>()
+}
+
+/// The LEN bits in DR7 for a watched region of `len` bytes.
+fn len_bits(len: u64) -> Result<u64, &'static str> {
+    match len {
+        1 => Ok(0b00),
+        2 => Ok(0b01),
+        4 => Ok(0b11),
+        8 => Ok(0b10),
+        _ => Err("Hardware breakpoints can only cover 1, 2, 4 or 8 bytes"),
+    }
+}
+
+pub fn check_watchable(addr: u64, len: u64) -> Result<(), &'static str> {
+    len_bits(len)?;
+    if !addr.is_multiple_of(len) {
+        return Err("Hardware watchpoint address must be aligned to its size");
+    }
+    Ok(())
+}
+
+/// `dr7` with `slot` enabled and set to trap on `trigger` over `len` bytes.
+fn enable_in_dr7(dr7: u64, slot: usize, trigger: Trigger, len: u64) -> Result<u64, &'static str> {
+    let control_shift = 16 + slot * 4;
+    let mut dr7 = dr7 & !(0b1111 << control_shift);
+    dr7 |= ((len_bits(len)? << 2) | trigger as u64) << control_shift;
+    dr7 |= 1 << (slot * 2);
+    Ok(dr7)
+}
+
+/// Points `slot` at `addr` and enables it in DR7.
+pub fn set_slot(pid: Pid, slot: usize, addr: u64, trigger: Trigger, len: u64) -> Result<(), Box<dyn std::error::Error>> {
+    assert!(slot < NUM_SLOTS);
+    // Execution breakpoints must use a length of 1
+    let len = if trigger == Trigger::Execute { 1 } else { len };
+
+    ptrace::pokeuser(pid, debugreg_offset(slot), addr)?;
+
+    let dr7 = ptrace::peekuser(pid, debugreg_offset(DR7_INDEX))?;
+    let dr7 = enable_in_dr7(dr7, slot, trigger, len)?;
+    ptrace::pokeuser(pid, debugreg_offset(DR7_INDEX), dr7)?;
+    Ok(())
+}
+
+/// Turns `slot` on or off in DR7 without forgetting what it points at.
+pub fn set_slot_enabled(pid: Pid, slot: usize, enabled: bool) -> Result<(), ptrace::Error> {
+    assert!(slot < NUM_SLOTS);
+    let mut dr7 = ptrace::peekuser(pid, debugreg_offset(DR7_INDEX))?;
+    match enabled {
+        true => dr7 |= 1 << (slot * 2),
+        false => dr7 &= !(1 << (slot * 2)),
+    }
+    ptrace::pokeuser(pid, debugreg_offset(DR7_INDEX), dr7)
+}
+
+/// Disables `slot` in DR7.
+pub fn clear_slot(pid: Pid, slot: usize) -> Result<(), ptrace::Error> {
+    assert!(slot < NUM_SLOTS);
+    let mut dr7 = ptrace::peekuser(pid, debugreg_offset(DR7_INDEX))?;
+    dr7 &= !(0b11 << (slot * 2));
+    ptrace::pokeuser(pid, debugreg_offset(DR7_INDEX), dr7)?;
+    ptrace::pokeuser(pid, debugreg_offset(slot), 0)
+}
+
+/// Clears DR6. Its bits are sticky, so traps taken while single-stepping would
+/// otherwise still be reported after the next continue.
+fn clear_status(pid: Pid) -> Result<(), ptrace::Error> {
+    ptrace::pokeuser(pid, debugreg_offset(DR6_INDEX), 0)
+}
+
+/// Returns the slot that caused the last debug trap, if any, and clears DR6 so the
+/// next trap starts from a clean status.
+pub fn take_triggered_slot(pid: Pid) -> Result<Option<usize>, ptrace::Error> {
+    let dr6 = ptrace::peekuser(pid, debugreg_offset(DR6_INDEX))?;
+    if dr6 & 0b1111 == 0 {
+        return Ok(None);
+    }
+    clear_status(pid)?;
+    Ok((0..NUM_SLOTS).find(|slot| dr6 & (1 << slot) != 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn len_bits_covers_the_four_sizes() {
+        assert_eq!(len_bits(1), Ok(0b00));
+        assert_eq!(len_bits(2), Ok(0b01));
+        assert_eq!(len_bits(4), Ok(0b11));
+        assert_eq!(len_bits(8), Ok(0b10));
+        assert!(len_bits(3).is_err());
+        assert!(len_bits(16).is_err());
+    }
+
+    #[test]
+    fn watchable_needs_aligned_address() {
+        assert!(check_watchable(0x1000, 8).is_ok());
+        assert!(check_watchable(0x1002, 2).is_ok());
+        assert!(check_watchable(0x1003, 1).is_ok());
+        assert!(check_watchable(0x1004, 8).is_err());
+        assert!(check_watchable(0x1000, 3).is_err());
+    }
+
+    #[test]
+    fn enable_packs_control_bits() {
+        // Slot 0, write, 4 bytes: L0 set, R/W0 = 01, LEN0 = 11
+        assert_eq!(enable_in_dr7(0, 0, Trigger::Write, 4), Ok(0b1101 << 16 | 0b01));
+        // Slot 3, read/write, 8 bytes: L3 set, R/W3 = 11, LEN3 = 10
+        assert_eq!(enable_in_dr7(0, 3, Trigger::ReadWrite, 8), Ok(0b1011 << 28 | 0b01 << 6));
+        // Slot 1, execute, 1 byte
+        assert_eq!(enable_in_dr7(0, 1, Trigger::Execute, 1), Ok(0b01 << 2));
+    }
+
+    #[test]
+    fn enable_keeps_other_slots() {
+        let dr7 = enable_in_dr7(0, 0, Trigger::Write, 8).unwrap();
+        let dr7 = enable_in_dr7(dr7, 2, Trigger::ReadWrite, 1).unwrap();
+        assert_eq!(dr7, 0b1001 << 16 | 0b0011 << 24 | 0b01 | 0b01 << 4);
+    }
+
+    #[test]
+    fn enable_replaces_old_control_bits() {
+        let dr7 = enable_in_dr7(0, 1, Trigger::ReadWrite, 8).unwrap();
+        let dr7 = enable_in_dr7(dr7, 1, Trigger::Write, 2).unwrap();
+        assert_eq!(dr7, 0b0101 << 20 | 0b01 << 2);
+    }
+
+    #[test]
+    fn enable_rejects_bad_length() {
+        assert!(enable_in_dr7(0, 0, Trigger::Write, 5).is_err());
+    }
+}
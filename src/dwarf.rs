@@ -6,6 +6,14 @@ use std::collections::HashMap;
 use std::error::Error;
 use std::fmt::Display;
 
+pub mod location;
+pub mod types;
+pub mod unwind;
+pub mod variables;
+
+use types::{ReturnLocation, TypeInfo};
+use unwind::CallFrameInfo;
+
 const EMPTY_ARR: [u8; 0] = [];
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -34,17 +42,30 @@ pub type Dwarf = gimli::Dwarf<StaticEndianSlice>;
 
 pub struct DwarfInfo {
     pub dwarf: Dwarf,
+    /// The ELF entry point (`e_entry`), before adding the base address.
+    pub entry_point: u64,
+    /// How to unwind the stack from any address in the program.
+    pub cfi: CallFrameInfo,
     function_cache: HashMap<String, Vec<u64>>,
 }
 
 impl DwarfInfo {
-    pub fn new(dwarf: Dwarf) -> Self {
+    pub fn new(dwarf: Dwarf, entry_point: u64, cfi: CallFrameInfo) -> Self {
         Self {
             dwarf,
+            entry_point,
+            cfi,
             function_cache: HashMap::new(),
         }
     }
 
+    /// Reads the debug info and entry point of the ELF file at `filename`.
+    pub fn load(filename: &str) -> Result<Self, Box<dyn Error>> {
+        let elf = parse_elf(filename)?;
+        let dwarf = load_dwarf(&elf)?;
+        Ok(Self::new(dwarf, elf.entry(), CallFrameInfo::load(&elf)))
+    }
+
     pub fn function_addresses(&mut self, function: &str) -> Result<Vec<u64>, gimli::Error> {
         if let Some(addresses) = self.function_cache.get(function).cloned() {
             return Ok(addresses);
@@ -59,23 +80,48 @@ impl DwarfInfo {
             .expect("Just inserted")
             .clone())
     }
+
+    pub fn line_addresses(
+        &self,
+        file: Option<&str>,
+        line: u64,
+    ) -> Result<Option<(u64, Vec<u64>)>, gimli::Error> {
+        line_addresses(&self.dwarf, file, line)
+    }
+
+    /// The first address after the prologue of the function starting at `low_pc`,
+    /// i.e. where the first line of its body begins.
+    pub fn skip_prologue(&self, low_pc: u64) -> Result<u64, gimli::Error> {
+        let high_pc = match find_subprogram_at_pc(&self.dwarf, low_pc, 0)? {
+            Some(subprogram) => subprogram.range.1,
+            None => return Ok(low_pc),
+        };
+        skip_prologue(&self.dwarf, low_pc, high_pc)
+    }
 }
 
 pub fn read_dwarf(filename: &str) -> Result<Dwarf, Box<dyn Error>> {
+    let elf = parse_elf(filename)?;
+    Ok(load_dwarf(&elf)?)
+}
+
+/// Maps the file at `filename` for the rest of the program and parses it.
+fn parse_elf(filename: &str) -> Result<object::File<'static>, Box<dyn Error>> {
     let mut file = std::fs::File::open(filename)?;
 
     let mapping = unsafe { mmap::Mmap::map(&mut file) };
     let mmap_slice: &'static [u8] = mapping.leak();
 
-    let elf = object::File::parse(mmap_slice)?;
+    Ok(object::File::parse(mmap_slice)?)
+}
+
+fn load_dwarf(elf: &object::File<'static>) -> Result<Dwarf, object::Error> {
     let endianness = Endianness::from(elf.endianness());
 
-    let dwarf = gimli::Dwarf::load(|id| match elf.section_by_name(id.name()) {
+    gimli::Dwarf::load(|id| match elf.section_by_name(id.name()) {
         Some(section) => Ok(gimli::EndianSlice::new(section.data()?, endianness)),
         None => Ok(gimli::EndianSlice::new(&EMPTY_ARR, endianness)),
-    });
-
-    dwarf
+    })
 }
 
 pub fn process_dwarf_test<R>(dwarf: &mut Dwarf) -> Result<(), gimli::Error>
@@ -169,6 +215,104 @@ pub fn function_names_to_addresses(
 }
 
 
+/// Finds the addresses of the statements on `line` of `file` (any file if `None`).
+/// If the line has no code, the next line that does is used instead. Every place the
+/// line was emitted is returned, e.g. each inlined copy. Returns the line that was
+/// actually used together with its addresses.
+pub fn line_addresses(
+    dwarf: &Dwarf,
+    file: Option<&str>,
+    line: u64,
+) -> Result<Option<(u64, Vec<u64>)>, gimli::Error> {
+    let mut units = dwarf.units();
+    let mut candidates: Vec<(u64, u64)> = Vec::new();
+
+    while let Some(header) = units.next()? {
+        let unit = dwarf.unit(header)?;
+        let Some(program) = unit.line_program.as_ref() else {
+            continue;
+        };
+
+        let mut rows = program.clone().rows();
+        let mut previous: Option<(u64, u64)> = None;
+        while let Some((header, row)) = rows.next_row()? {
+            if row.end_sequence() {
+                previous = None;
+                continue;
+            }
+            let Some(row_line) = row.line().map(u64::from) else {
+                continue;
+            };
+            let current = (row.file_index(), row_line);
+
+            // Only the first row of a run of rows for the same line is a place to stop
+            let starts_line = previous != Some(current);
+            previous = Some(current);
+            if !starts_line || !row.is_stmt() || row_line < line {
+                continue;
+            }
+
+            if let Some(file) = file {
+                let Some(file_entry) = header.file(row.file_index()) else {
+                    continue;
+                };
+                let path = dwarf.attr_string(&unit, file_entry.path_name())?;
+                let path = path.to_string_lossy();
+                if path != file && !path.ends_with(&format!("/{file}")) {
+                    continue;
+                }
+            }
+
+            candidates.push((row_line, row.address()));
+        }
+    }
+
+    let Some(best_line) = candidates.iter().map(|(line, _)| *line).min() else {
+        return Ok(None);
+    };
+    let mut addresses: Vec<u64> = candidates
+        .into_iter()
+        .filter(|(line, _)| *line == best_line)
+        .map(|(_, address)| address)
+        .collect();
+    addresses.sort_unstable();
+    addresses.dedup();
+
+    Ok(Some((best_line, addresses)))
+}
+
+/// The end of the prologue of the function at `low_pc..high_pc`: the first row
+/// marked `prologue_end` if the compiler emitted any, otherwise the first statement
+/// after the entry.
+fn skip_prologue(dwarf: &Dwarf, low_pc: u64, high_pc: u64) -> Result<u64, gimli::Error> {
+    let mut units = dwarf.units();
+    let mut prologue_end: Option<u64> = None;
+    let mut first_statement: Option<u64> = None;
+
+    while let Some(header) = units.next()? {
+        let unit = dwarf.unit(header)?;
+        let Some(program) = unit.line_program.as_ref() else {
+            continue;
+        };
+
+        let mut rows = program.clone().rows();
+        while let Some((_, row)) = rows.next_row()? {
+            let address = row.address();
+            if row.end_sequence() || address <= low_pc || address >= high_pc {
+                continue;
+            }
+            if row.prologue_end() {
+                prologue_end = Some(prologue_end.map_or(address, |end| end.min(address)));
+            }
+            if row.is_stmt() {
+                first_statement = Some(first_statement.map_or(address, |first| first.min(address)));
+            }
+        }
+    }
+
+    Ok(prologue_end.or(first_statement).unwrap_or(low_pc))
+}
+
 pub fn find_function_at_pc(
     dwarf: &Dwarf,
     pc: u64,
@@ -178,6 +322,9 @@ pub fn find_function_at_pc(
 
     while let Some(header) = units.next()? {
         let unit: gimli::Unit<StaticEndianSlice> = dwarf.unit(header)?;
+        if unit_excludes(dwarf, &unit, pc.wrapping_sub(base))? {
+            continue;
+        }
         let mut entries = unit.entries();
 
         let mut best_func: Option<StaticEndianSlice> = None;
@@ -190,12 +337,13 @@ pub fn find_function_at_pc(
             let Some(function_name) = function_name_from_entry(dwarf, &unit, entry)? else {
                 continue;
             };
-            let Some((lo_pc_offset, hi_pc_offset)) = function_lo_hi_pc(entry)? else {
+            let address = pc.wrapping_sub(base);
+            let Some(range) = die_range_containing(dwarf, &unit, entry, address)? else {
                 continue;
             };
 
-            let (lo_pc, hi_pc) = (lo_pc_offset + base, hi_pc_offset + base);
-            if !(lo_pc..hi_pc).contains(&pc) || best_range.is_some_and(|(lo, _)| lo > lo_pc) {
+            let (lo_pc, hi_pc) = (range.begin + base, range.end + base);
+            if best_range.is_some_and(|(lo, _)| lo > lo_pc) {
                 continue;
             }
             best_range = Some((lo_pc, hi_pc));
@@ -216,13 +364,212 @@ pub fn find_function_at_pc(
             file: header
                 .file(row.file_index())
                 .and_then(|file_entry| dwarf.attr_string(&unit, file_entry.path_name()).ok()),
-            function: best_func
+            function: best_func,
+            function_range: best_range,
         }));
     }
 
     Ok(None)
 }
 
+/// The concrete (non-inlined) function containing a PC.
+#[derive(Debug, Clone)]
+pub struct Subprogram {
+    pub name: Option<StaticEndianSlice>,
+    /// Real address range of the part of the function containing the PC.
+    pub range: (u64, u64),
+    pub return_type: TypeInfo,
+    pub return_location: ReturnLocation,
+}
+
+pub fn find_subprogram_at_pc(
+    dwarf: &Dwarf,
+    pc: u64,
+    base: u64,
+) -> Result<Option<Subprogram>, gimli::Error> {
+    let mut units = dwarf.units();
+
+    while let Some(header) = units.next()? {
+        let unit: gimli::Unit<StaticEndianSlice> = dwarf.unit(header)?;
+        if unit_excludes(dwarf, &unit, pc.wrapping_sub(base))? {
+            continue;
+        }
+        let mut entries = unit.entries();
+
+        while let Some((_, entry)) = entries.next_dfs()? {
+            if entry.tag() != gimli::DW_TAG_subprogram {
+                continue;
+            }
+            // Functions split into hot and cold parts have several ranges
+            let address = pc.wrapping_sub(base);
+            let Some(range) = die_range_containing(dwarf, &unit, entry, address)? else {
+                continue;
+            };
+
+            return Ok(Some(Subprogram {
+                name: function_name_from_entry(dwarf, &unit, entry)?,
+                range: (range.begin + base, range.end + base),
+                return_type: TypeInfo::of_entry(dwarf, &unit, entry)?,
+                return_location: ReturnLocation::of_entry(dwarf, &unit, entry)?,
+            }));
+        }
+    }
+
+    Ok(None)
+}
+
+/// A function in the chain of inlined calls at a PC. Each inlined function is shown
+/// as a frame of its own, though they all share the real frame of the subprogram.
+#[derive(Debug, Clone)]
+pub struct InlineFrame {
+    pub function: Option<StaticEndianSlice>,
+    /// Where execution is in the function: the line table row for the innermost
+    /// frame, and the call site of the function inlined into it for the others.
+    pub file: Option<StaticEndianSlice>,
+    pub line: Option<u64>,
+    /// Whether this is a `DW_TAG_inlined_subroutine` rather than the subprogram.
+    pub inlined: bool,
+}
+
+impl InlineFrame {
+    /// A short description for backtraces, e.g. `foo (file.c:12)`.
+    pub fn summary(&self) -> String {
+        let filename = static_endian_slice_to_string_lossy_or(self.file.as_ref(), "??");
+        let func = static_endian_slice_to_string_lossy_or(self.function.as_ref(), "??");
+        match self.line {
+            Some(line) => format!("{func} ({filename}:{line})"),
+            None => format!("{func} ({filename})"),
+        }
+    }
+}
+
+/// The chain of functions at `pc`, innermost first: each nested
+/// `DW_TAG_inlined_subroutine`, then the subprogram they were inlined into.
+pub fn find_inline_frames_at_pc(
+    dwarf: &Dwarf,
+    pc: u64,
+    base: u64,
+) -> Result<Vec<InlineFrame>, gimli::Error> {
+    let mut units = dwarf.units();
+
+    while let Some(header) = units.next()? {
+        let unit: gimli::Unit<StaticEndianSlice> = dwarf.unit(header)?;
+        let chain = functions_containing(dwarf, &unit, pc.wrapping_sub(base))?;
+        if chain.is_empty() {
+            continue;
+        }
+
+        let header = unit.line_program.as_ref().map(|program| program.header());
+        let file_name = |index: u64| {
+            header
+                .and_then(|header| header.file(index))
+                .and_then(|file_entry| dwarf.attr_string(&unit, file_entry.path_name()).ok())
+        };
+
+        let (mut file, mut line) = match find_row_at_pc(&unit, pc, base)? {
+            Some((_, row)) => (file_name(row.file_index()), row.line().map(u64::from)),
+            None => (None, None),
+        };
+        let mut frames = Vec::new();
+        for &offset in chain.iter().rev() {
+            let entry = unit.entry(offset)?;
+            frames.push(InlineFrame {
+                function: function_name_from_entry(dwarf, &unit, &entry)?,
+                file,
+                line,
+                inlined: entry.tag() == gimli::DW_TAG_inlined_subroutine,
+            });
+            // The function this one was inlined into is at the call site
+            file = match entry.attr_value(gimli::DW_AT_call_file)? {
+                Some(gimli::AttributeValue::FileIndex(index)) => file_name(index),
+                Some(value) => value.udata_value().and_then(file_name),
+                None => None,
+            };
+            line = entry.attr_value(gimli::DW_AT_call_line)?.and_then(|value| value.udata_value());
+        }
+        return Ok(frames);
+    }
+
+    Ok(Vec::new())
+}
+
+/// The subprogram of `unit` containing `address` followed by each inlined
+/// subroutine nested in it there, outermost first. `address` is before adding the base.
+fn functions_containing(
+    dwarf: &Dwarf,
+    unit: &gimli::Unit<StaticEndianSlice>,
+    address: u64,
+) -> Result<Vec<gimli::UnitOffset>, gimli::Error> {
+    if unit_excludes(dwarf, unit, address)? {
+        return Ok(Vec::new());
+    }
+    let mut entries = unit.entries();
+
+    // The functions containing `address` with their depth
+    let mut chain: Vec<(isize, gimli::UnitOffset)> = Vec::new();
+    let mut depth = 0;
+    while let Some((delta, entry)) = entries.next_dfs()? {
+        depth += delta;
+        if chain.last().is_some_and(|&(function_depth, _)| function_depth >= depth) {
+            // Left the innermost function found, which had nothing nested at `address`
+            break;
+        }
+        if ![gimli::DW_TAG_subprogram, gimli::DW_TAG_inlined_subroutine].contains(&entry.tag())
+            || !die_contains(dwarf, unit, entry, address)?
+        {
+            continue;
+        }
+        chain.push((depth, entry.offset()));
+    }
+    Ok(chain.into_iter().map(|(_, offset)| offset).collect())
+}
+
+/// Whether the compile unit `unit` says which addresses it covers and `address` is
+/// not one of them, so none of its functions need to be looked at.
+fn unit_excludes(
+    dwarf: &Dwarf,
+    unit: &gimli::Unit<StaticEndianSlice>,
+    address: u64,
+) -> Result<bool, gimli::Error> {
+    let mut entries = unit.entries();
+    let Some((_, root)) = entries.next_dfs()? else {
+        return Ok(true);
+    };
+    // A unit with only a `DW_AT_low_pc` is just giving its functions a base address
+    if root.attr_value(gimli::DW_AT_ranges)?.is_none()
+        && root.attr_value(gimli::DW_AT_high_pc)?.is_none()
+    {
+        return Ok(false);
+    }
+    Ok(!die_contains(dwarf, unit, root, address)?)
+}
+
+/// Whether the address ranges of `entry` contain `address`, before adding the base.
+fn die_contains(
+    dwarf: &Dwarf,
+    unit: &gimli::Unit<StaticEndianSlice>,
+    entry: &gimli::DebuggingInformationEntry<StaticEndianSlice>,
+    address: u64,
+) -> Result<bool, gimli::Error> {
+    Ok(die_range_containing(dwarf, unit, entry, address)?.is_some())
+}
+
+/// The address range of `entry` containing `address`, before adding the base.
+fn die_range_containing(
+    dwarf: &Dwarf,
+    unit: &gimli::Unit<StaticEndianSlice>,
+    entry: &gimli::DebuggingInformationEntry<StaticEndianSlice>,
+    address: u64,
+) -> Result<Option<gimli::Range>, gimli::Error> {
+    let mut ranges = dwarf.die_ranges(unit, entry)?;
+    while let Some(range) = ranges.next()? {
+        if (range.begin..range.end).contains(&address) {
+            return Ok(Some(range));
+        }
+    }
+    Ok(None)
+}
+
 fn find_row_at_pc(
     unit: &gimli::Unit<StaticEndianSlice>,
     pc: u64,
@@ -293,37 +640,42 @@ fn function_name_from_entry(
     Ok(None)
 }
 
-fn function_lo_hi_pc(
-    entry: &gimli::DebuggingInformationEntry<StaticEndianSlice>,
-) -> gimli::Result<Option<(u64, u64)>> {
-    let lo = match entry.attr_value(gimli::DW_AT_low_pc)? {
-        Some(gimli::AttributeValue::Addr(lo)) => lo,
-        _ => return Ok(None),
-    };
-
-    let hi = match entry.attr_value(gimli::DW_AT_high_pc)? {
-        Some(gimli::AttributeValue::Addr(hi)) => hi,
-        Some(gimli::AttributeValue::Udata(hi)) => lo + hi,
-        Some(gimli::AttributeValue::Data1(hi)) => lo + hi as u64,
-        Some(gimli::AttributeValue::Data2(hi)) => lo + hi as u64,
-        Some(gimli::AttributeValue::Data4(hi)) => lo + hi as u64,
-        Some(gimli::AttributeValue::Data8(hi)) => lo + hi,
-        Some(gimli::AttributeValue::Sdata(hi)) if hi < 0 => {
-            return Err(gimli::Error::InvalidAddressRange)
-        }
-        Some(gimli::AttributeValue::Sdata(hi)) => lo + hi as u64,
-        _ => return Ok(None),
-    };
-
-    Ok(Some((lo, hi)))
-}
-
 #[derive(Debug, Clone)]
 pub struct CodePoint {
     pub row: gimli::LineRow,
     pub real_addr: u64,
     pub file: Option<StaticEndianSlice>,
     pub function: Option<StaticEndianSlice>,
+    /// Real address range of the innermost subprogram or inlined subroutine.
+    pub function_range: Option<(u64, u64)>,
+}
+
+impl CodePoint {
+    /// The `(file, line)` pair this code point belongs to.
+    pub fn line_key(&self) -> (Option<StaticEndianSlice>, Option<u64>) {
+        (self.file, self.row.line().map(u64::from))
+    }
+
+    /// Whether this code point is at the first instruction of a statement in the
+    /// line table, i.e. a place where a source-level step should stop.
+    pub fn is_statement_start(&self, base: u64) -> bool {
+        self.row.is_stmt() && self.row.address() + base == self.real_addr
+    }
+
+    /// Whether this code point is the entry of a function or inlined subroutine.
+    pub fn is_function_entry(&self) -> bool {
+        self.function_range.is_some_and(|(lo, _)| lo == self.real_addr)
+    }
+
+    /// A short description for stop messages, e.g. `foo (file.c:12)`.
+    pub fn summary(&self) -> String {
+        let filename = static_endian_slice_to_string_lossy_or(self.file.as_ref(), "??");
+        let func = static_endian_slice_to_string_lossy_or(self.function.as_ref(), "??");
+        match self.row.line() {
+            Some(line) => format!("{func} ({filename}:{line})"),
+            None => format!("{func} ({filename})"),
+        }
+    }
 }
 
 impl Display for CodePoint {
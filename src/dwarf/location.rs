@@ -0,0 +1,372 @@
+use gimli::{EvaluationResult, Expression, Location, Piece, Value, ValueType};
+
+use super::StaticEndianSlice;
+use crate::registers::Register;
+
+/// What is shown for a value the compiler didn't keep around at the PC.
+pub const OPTIMIZED_OUT: &str = "optimized out";
+
+/// The state of the frame a location is evaluated in.
+pub trait LocationContext {
+    /// The value of `register` in the frame.
+    fn register(&mut self, register: Register) -> Result<u64, String>;
+    /// The 16 bytes of XMM register `number` in the frame.
+    fn xmm_register(&mut self, number: usize) -> Result<[u8; 16], String>;
+    /// Fills `bytes` from the process's memory at `address`.
+    fn read_memory(&mut self, address: u64, bytes: &mut [u8]) -> Result<(), String>;
+    /// The canonical frame address, for `DW_OP_call_frame_cfa`.
+    fn cfa(&mut self) -> Result<u64, String>;
+    /// Adds the base address to an address from the debug info, for `DW_OP_addr`.
+    fn relocate(&mut self, address: u64) -> u64;
+    /// The value `expression` had on entry to the function, for `DW_OP_entry_value`.
+    fn entry_value(
+        &mut self,
+        expression: Expression<StaticEndianSlice>,
+        encoding: gimli::Encoding,
+    ) -> Result<u64, String>;
+    /// The type of the `DW_TAG_base_type` at `offset` in the unit the expression is
+    /// from, for typed operations such as `DW_OP_deref_type`.
+    fn base_type(&mut self, offset: gimli::UnitOffset) -> Result<ValueType, String>;
+}
+
+/// Evaluates the location description `expression` and reads the `size` bytes of
+/// the value it describes, which may be pieced together from several places.
+/// `frame_base` is the location `DW_OP_fbreg` is relative to.
+pub fn read_location(
+    expression: Expression<StaticEndianSlice>,
+    encoding: gimli::Encoding,
+    size: usize,
+    frame_base: Option<Expression<StaticEndianSlice>>,
+    context: &mut impl LocationContext,
+) -> Result<Vec<u8>, String> {
+    let pieces = evaluate(expression, encoding, frame_base, context)?;
+    let mut bytes = Vec::with_capacity(size);
+    for piece in pieces {
+        // Only the last piece may leave its size to the size of the value
+        let piece_size = match piece.size_in_bits {
+            Some(bits) => (bits / 8) as usize,
+            None => size.saturating_sub(bytes.len()),
+        };
+        let offset = (piece.bit_offset.unwrap_or(0) / 8) as usize;
+        match piece.location {
+            Location::Empty => return Err(OPTIMIZED_OUT.to_string()),
+            Location::Register { register } => {
+                let value = register_bytes(register, context)?;
+                bytes.extend(value.iter().skip(offset).take(piece_size));
+            }
+            Location::Address { address } => {
+                let mut piece_bytes = vec![0; piece_size];
+                context.read_memory(address + offset as u64, &mut piece_bytes)?;
+                bytes.extend(piece_bytes);
+            }
+            Location::Value { value } => {
+                let value = value_bits(value)?;
+                bytes.extend(value.to_le_bytes().iter().skip(offset).take(piece_size));
+            }
+            Location::Bytes { value } => {
+                bytes.extend(value.slice().iter().skip(offset).take(piece_size));
+            }
+            Location::ImplicitPointer { .. } => {
+                return Err("implicit pointers are not supported".to_string())
+            }
+        }
+    }
+    bytes.resize(size, 0);
+    Ok(bytes)
+}
+
+/// Evaluates `expression` for the value it computes rather than a location, as
+/// `DW_AT_frame_base` and `DW_AT_call_value` are: an address or a register is the
+/// value itself.
+pub fn evaluate_value(
+    expression: Expression<StaticEndianSlice>,
+    encoding: gimli::Encoding,
+    frame_base: Option<Expression<StaticEndianSlice>>,
+    context: &mut impl LocationContext,
+) -> Result<u64, String> {
+    let pieces = evaluate(expression, encoding, frame_base, context)?;
+    let [piece] = pieces.as_slice() else {
+        return Err("expected a single value".to_string());
+    };
+    match piece.location {
+        Location::Address { address } => Ok(address),
+        Location::Register { register } => Ok(low_word(&register_bytes(register, context)?)),
+        Location::Value { value } => value_bits(value),
+        Location::Empty => Err(OPTIMIZED_OUT.to_string()),
+        _ => Err("expected a single value".to_string()),
+    }
+}
+
+/// Runs gimli's evaluator over `expression`, answering what it asks for from
+/// `context`.
+fn evaluate(
+    expression: Expression<StaticEndianSlice>,
+    encoding: gimli::Encoding,
+    frame_base: Option<Expression<StaticEndianSlice>>,
+    context: &mut impl LocationContext,
+) -> Result<Vec<Piece<StaticEndianSlice>>, String> {
+    let mut evaluation = expression.evaluation(encoding);
+    let mut result = evaluation.evaluate();
+    loop {
+        result = match result.map_err(|e| e.to_string())? {
+            EvaluationResult::Complete => return Ok(evaluation.result()),
+            EvaluationResult::RequiresMemory { address, size, base_type, .. } => {
+                let size = size as usize;
+                if size > 8 {
+                    return Err(format!("can't read a {size} byte value in a location expression"));
+                }
+                let mut bytes = [0; 8];
+                context.read_memory(address, &mut bytes[..size])?;
+                evaluation.resume_with_memory(typed_value(&bytes[..size], base_type, context)?)
+            }
+            EvaluationResult::RequiresRegister { register, base_type } => {
+                let bytes = register_bytes(register, context)?;
+                evaluation.resume_with_register(typed_value(&bytes, base_type, context)?)
+            }
+            EvaluationResult::RequiresFrameBase => {
+                let frame_base = frame_base.ok_or("the function has no frame base")?;
+                let value = evaluate_value(frame_base, encoding, None, context)?;
+                evaluation.resume_with_frame_base(value)
+            }
+            EvaluationResult::RequiresCallFrameCfa => {
+                evaluation.resume_with_call_frame_cfa(context.cfa()?)
+            }
+            EvaluationResult::RequiresEntryValue(expression) => {
+                let value = context.entry_value(expression, encoding)?;
+                evaluation.resume_with_entry_value(Value::Generic(value))
+            }
+            EvaluationResult::RequiresRelocatedAddress(address) => {
+                evaluation.resume_with_relocated_address(context.relocate(address))
+            }
+            EvaluationResult::RequiresBaseType(offset) => {
+                evaluation.resume_with_base_type(context.base_type(offset)?)
+            }
+            EvaluationResult::RequiresTls(_) => {
+                return Err("thread-local variables are not supported".to_string())
+            }
+            other => return Err(format!("unsupported location expression ({other:?})")),
+        };
+    }
+}
+
+/// The value of the base type at `base_type` stored in `bytes`, or a generic value
+/// made of their first 8 bytes when there's no base type.
+fn typed_value(
+    bytes: &[u8],
+    base_type: gimli::UnitOffset,
+    context: &mut impl LocationContext,
+) -> Result<Value, String> {
+    if base_type == gimli::UnitOffset(0) {
+        return Ok(Value::Generic(low_word(bytes)));
+    }
+    let value_type = context.base_type(base_type)?;
+    Value::parse(value_type, gimli::EndianSlice::new(bytes, gimli::LittleEndian))
+        .map_err(|e| e.to_string())
+}
+
+/// The first 8 bytes of `bytes`, padded with zeroes, as a little endian word.
+fn low_word(bytes: &[u8]) -> u64 {
+    let mut word = [0; 8];
+    let len = bytes.len().min(8);
+    word[..len].copy_from_slice(&bytes[..len]);
+    u64::from_le_bytes(word)
+}
+
+/// The bytes of DWARF register `register`: 8 for a general purpose register and 16
+/// for an XMM register.
+fn register_bytes(
+    register: gimli::Register,
+    context: &mut impl LocationContext,
+) -> Result<Vec<u8>, String> {
+    // DWARF numbers XMM0 to XMM15 from 17
+    match register.0 {
+        17..=32 => Ok(context.xmm_register(register.0 as usize - 17)?.to_vec()),
+        _ => Ok(context.register(dwarf_register(register)?)?.to_le_bytes().to_vec()),
+    }
+}
+
+/// The bits of `value`, with floats kept as they are stored.
+fn value_bits(value: Value) -> Result<u64, String> {
+    match value {
+        Value::F32(value) => Ok(value.to_bits() as u64),
+        Value::F64(value) => Ok(value.to_bits()),
+        value => value.to_u64(!0).map_err(|e| e.to_string()),
+    }
+}
+
+/// Our register for DWARF register `register`, if it's a general purpose one.
+pub fn dwarf_register(register: gimli::Register) -> Result<Register, String> {
+    Register::from_dwarf(register.0 as i32)
+        .ok_or_else(|| format!("unsupported DWARF register {}", register.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dwarf::Endianness;
+
+    const ENCODING: gimli::Encoding = gimli::Encoding {
+        format: gimli::Format::Dwarf32,
+        version: 4,
+        address_size: 8,
+    };
+
+    const RAX: u64 = 0x8877_6655_4433_2211;
+    const RDX: u64 = 0x1122_3344_5566_7788;
+    const RSP: u64 = 0x7ff0_0000;
+    const BASE: u64 = 0x5555_0000_0000;
+
+    /// A frame with a few known registers and 64 bytes of memory at RSP, where
+    /// each byte holds the low bits of its address.
+    struct TestFrame;
+
+    impl LocationContext for TestFrame {
+        fn register(&mut self, register: Register) -> Result<u64, String> {
+            match register {
+                Register::RAX => Ok(RAX),
+                Register::RDX => Ok(RDX),
+                Register::RSP => Ok(RSP),
+                _ => Err(format!("{register:?} not saved")),
+            }
+        }
+
+        fn xmm_register(&mut self, number: usize) -> Result<[u8; 16], String> {
+            Ok(std::array::from_fn(|i| (number * 16 + i) as u8))
+        }
+
+        fn read_memory(&mut self, address: u64, bytes: &mut [u8]) -> Result<(), String> {
+            if address < RSP || address + bytes.len() as u64 > RSP + 64 {
+                return Err(format!("Cannot access memory at address 0x{address:x}"));
+            }
+            for (i, byte) in bytes.iter_mut().enumerate() {
+                *byte = (address + i as u64) as u8;
+            }
+            Ok(())
+        }
+
+        fn cfa(&mut self) -> Result<u64, String> {
+            Ok(RSP + 32)
+        }
+
+        fn relocate(&mut self, address: u64) -> u64 {
+            BASE + address
+        }
+
+        fn entry_value(
+            &mut self,
+            _expression: Expression<StaticEndianSlice>,
+            _encoding: gimli::Encoding,
+        ) -> Result<u64, String> {
+            Err("no entry values".to_string())
+        }
+
+        fn base_type(&mut self, _offset: gimli::UnitOffset) -> Result<ValueType, String> {
+            Ok(ValueType::U32)
+        }
+    }
+
+    fn expression(bytes: &'static [u8]) -> Expression<StaticEndianSlice> {
+        Expression(gimli::EndianSlice::new(bytes, Endianness::Little))
+    }
+
+    fn read(bytes: &'static [u8], size: usize) -> Result<Vec<u8>, String> {
+        read_location(expression(bytes), ENCODING, size, None, &mut TestFrame)
+    }
+
+    #[test]
+    fn single_register_is_truncated_to_size() {
+        // DW_OP_reg0
+        assert_eq!(read(&[0x50], 4).unwrap(), RAX.to_le_bytes()[..4]);
+        assert_eq!(read(&[0x50], 8).unwrap(), RAX.to_le_bytes());
+    }
+
+    #[test]
+    fn register_pieces_are_concatenated() {
+        // DW_OP_reg0, DW_OP_piece 4, DW_OP_reg1, DW_OP_piece 2
+        let bytes = read(&[0x50, 0x93, 0x04, 0x51, 0x93, 0x02], 6).unwrap();
+        assert_eq!(bytes[..4], RAX.to_le_bytes()[..4]);
+        assert_eq!(bytes[4..], RDX.to_le_bytes()[..2]);
+    }
+
+    #[test]
+    fn bit_piece_offset_skips_low_bytes() {
+        // DW_OP_reg0, DW_OP_bit_piece 16 16
+        assert_eq!(read(&[0x50, 0x9d, 0x10, 0x10], 2).unwrap(), RAX.to_le_bytes()[2..4]);
+    }
+
+    #[test]
+    fn memory_and_value_pieces() {
+        // DW_OP_breg7 8, DW_OP_piece 2, DW_OP_lit5, DW_OP_stack_value, DW_OP_piece 2
+        let bytes = read(&[0x77, 0x08, 0x93, 0x02, 0x35, 0x9f, 0x93, 0x02], 4).unwrap();
+        assert_eq!(bytes, [0x08, 0x09, 0x05, 0x00]);
+    }
+
+    #[test]
+    fn short_pieces_are_padded() {
+        // DW_OP_reg1, DW_OP_piece 2
+        assert_eq!(read(&[0x51, 0x93, 0x02], 4).unwrap(), [0x88, 0x77, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn xmm_registers() {
+        // DW_OP_reg17 is XMM0, DW_OP_regx 18 is XMM1
+        assert_eq!(read(&[0x61], 8).unwrap(), [0, 1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(read(&[0x90, 0x12], 4).unwrap(), [16, 17, 18, 19]);
+        // DW_OP_reg17, DW_OP_piece 4, DW_OP_reg18, DW_OP_piece 4
+        let bytes = read(&[0x61, 0x93, 0x04, 0x62, 0x93, 0x04], 8).unwrap();
+        assert_eq!(bytes, [0, 1, 2, 3, 16, 17, 18, 19]);
+    }
+
+    #[test]
+    fn missing_piece_is_optimized_out() {
+        // DW_OP_piece 4, DW_OP_reg0, DW_OP_piece 4
+        assert_eq!(read(&[0x93, 0x04, 0x50, 0x93, 0x04], 8).unwrap_err(), OPTIMIZED_OUT);
+    }
+
+    #[test]
+    fn frame_base_and_cfa() {
+        // DW_OP_fbreg -16, with DW_OP_call_frame_cfa as the frame base
+        let frame_base = Some(expression(&[0x9c]));
+        let bytes =
+            read_location(expression(&[0x91, 0x70]), ENCODING, 2, frame_base, &mut TestFrame);
+        assert_eq!(bytes.unwrap(), [0x10, 0x11]);
+        assert_eq!(read(&[0x91, 0x70], 2).unwrap_err(), "the function has no frame base");
+    }
+
+    #[test]
+    fn addresses_are_relocated() {
+        // DW_OP_addr 0x10, DW_OP_stack_value
+        let bytes = read(&[0x03, 0x10, 0, 0, 0, 0, 0, 0, 0, 0x9f], 8).unwrap();
+        assert_eq!(bytes, (BASE + 0x10).to_le_bytes());
+    }
+
+    #[test]
+    fn typed_deref_uses_base_type() {
+        // DW_OP_breg7 0, DW_OP_deref_type 4 <0x30>, DW_OP_stack_value
+        assert_eq!(read(&[0x77, 0x00, 0xa6, 0x04, 0x30, 0x9f], 4).unwrap(), [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn wide_deref_is_an_error() {
+        // DW_OP_breg7 0, DW_OP_deref_size 16, DW_OP_stack_value
+        assert!(read(&[0x77, 0x00, 0x94, 0x10, 0x9f], 16).is_err());
+    }
+
+    #[test]
+    fn unsaved_register_is_an_error() {
+        // DW_OP_reg3 (RBX)
+        assert_eq!(read(&[0x53], 8).unwrap_err(), "RBX not saved");
+    }
+
+    #[test]
+    fn bad_memory_is_an_error() {
+        // DW_OP_breg7 64, DW_OP_piece 1
+        assert!(read(&[0x77, 0xc0, 0x00, 0x93, 0x01], 1).is_err());
+    }
+
+    #[test]
+    fn evaluate_value_reads_registers() {
+        let value = evaluate_value(expression(&[0x51]), ENCODING, None, &mut TestFrame);
+        assert_eq!(value, Ok(RDX));
+    }
+}
@@ -0,0 +1,423 @@
+use std::fmt::Display;
+
+use super::{Dwarf, StaticEndianSlice};
+
+type Unit = gimli::Unit<StaticEndianSlice>;
+
+/// A decoded `DW_AT_type`, enough to name a type and format a value of it.
+#[derive(Debug, Clone)]
+pub struct TypeInfo {
+    pub name: String,
+    pub size: u64,
+    pub kind: TypeKind,
+}
+
+#[derive(Debug, Clone)]
+pub enum TypeKind {
+    Void,
+    Signed,
+    Unsigned,
+    Float,
+    Bool,
+    Char,
+    Pointer,
+    Enum(Vec<(String, i64)>),
+    /// Structs, unions, arrays and anything else we only show as raw bytes.
+    Aggregate,
+}
+
+impl TypeInfo {
+    pub fn void() -> Self {
+        Self {
+            name: "void".to_string(),
+            size: 0,
+            kind: TypeKind::Void,
+        }
+    }
+
+    /// The type for a Rust primitive name such as `i32` or `f64`, as used by the
+    /// `read` and `watch` commands.
+    pub fn primitive(name: &str) -> Option<Self> {
+        let (size, kind) = match name {
+            "i8" => (1, TypeKind::Signed),
+            "i16" => (2, TypeKind::Signed),
+            "i32" => (4, TypeKind::Signed),
+            "i64" | "isize" => (8, TypeKind::Signed),
+            "i128" => (16, TypeKind::Signed),
+            "u8" => (1, TypeKind::Unsigned),
+            "u16" => (2, TypeKind::Unsigned),
+            "u32" => (4, TypeKind::Unsigned),
+            "u64" | "usize" => (8, TypeKind::Unsigned),
+            "u128" => (16, TypeKind::Unsigned),
+            "f32" => (4, TypeKind::Float),
+            "f64" => (8, TypeKind::Float),
+            "bool" => (1, TypeKind::Bool),
+            "char" => (4, TypeKind::Char),
+            _ => return None,
+        };
+        Some(Self {
+            name: name.to_string(),
+            size,
+            kind,
+        })
+    }
+
+    /// Decodes the type referenced by the `DW_AT_type` attribute of `entry`. Entries
+    /// without one (e.g. functions returning nothing) are `void`.
+    pub fn of_entry(
+        dwarf: &Dwarf,
+        unit: &Unit,
+        entry: &gimli::DebuggingInformationEntry<StaticEndianSlice>,
+    ) -> Result<Self, gimli::Error> {
+        match entry.attr_value(gimli::DW_AT_type)? {
+            Some(gimli::AttributeValue::UnitRef(offset)) => Self::at_offset(dwarf, unit, offset),
+            _ => Ok(Self::void()),
+        }
+    }
+
+    pub fn at_offset(
+        dwarf: &Dwarf,
+        unit: &Unit,
+        offset: gimli::UnitOffset,
+    ) -> Result<Self, gimli::Error> {
+        let entry = unit.entry(offset)?;
+        let name = match entry.attr_value(gimli::DW_AT_name)? {
+            Some(attr) => Some(dwarf.attr_string(unit, attr)?.to_string_lossy().into_owned()),
+            None => None,
+        };
+        let size = entry
+            .attr_value(gimli::DW_AT_byte_size)?
+            .and_then(|attr| attr.udata_value())
+            .unwrap_or(0);
+
+        let ty = match entry.tag() {
+            gimli::DW_TAG_base_type => {
+                let kind = match entry.attr_value(gimli::DW_AT_encoding)? {
+                    Some(gimli::AttributeValue::Encoding(encoding)) => match encoding {
+                        gimli::DW_ATE_signed => TypeKind::Signed,
+                        gimli::DW_ATE_unsigned => TypeKind::Unsigned,
+                        gimli::DW_ATE_float => TypeKind::Float,
+                        gimli::DW_ATE_boolean => TypeKind::Bool,
+                        gimli::DW_ATE_signed_char
+                        | gimli::DW_ATE_unsigned_char
+                        | gimli::DW_ATE_UTF => TypeKind::Char,
+                        _ => TypeKind::Aggregate,
+                    },
+                    _ => TypeKind::Aggregate,
+                };
+                Self {
+                    name: name.unwrap_or_else(|| "<base>".to_string()),
+                    size,
+                    kind,
+                }
+            }
+            gimli::DW_TAG_pointer_type | gimli::DW_TAG_reference_type => {
+                let pointee = Self::of_entry(dwarf, unit, &entry)?;
+                let sigil = if entry.tag() == gimli::DW_TAG_pointer_type { "*" } else { "&" };
+                Self {
+                    name: name.unwrap_or_else(|| format!("{} {sigil}", pointee.name)),
+                    size: if size == 0 { 8 } else { size },
+                    kind: TypeKind::Pointer,
+                }
+            }
+            gimli::DW_TAG_typedef => Self {
+                name: name.unwrap_or_default(),
+                ..Self::of_entry(dwarf, unit, &entry)?
+            },
+            gimli::DW_TAG_const_type | gimli::DW_TAG_volatile_type => {
+                let qualifier = if entry.tag() == gimli::DW_TAG_const_type { "const" } else { "volatile" };
+                let inner = Self::of_entry(dwarf, unit, &entry)?;
+                Self {
+                    name: format!("{qualifier} {}", inner.name),
+                    ..inner
+                }
+            }
+            gimli::DW_TAG_enumeration_type => {
+                let mut enumerators = Vec::new();
+                let mut tree = unit.entries_tree(Some(offset))?;
+                let mut children = tree.root()?.children();
+                while let Some(child) = children.next()? {
+                    let child = child.entry();
+                    if child.tag() != gimli::DW_TAG_enumerator {
+                        continue;
+                    }
+                    let Some(attr) = child.attr_value(gimli::DW_AT_name)? else {
+                        continue;
+                    };
+                    let enumerator = dwarf.attr_string(unit, attr)?.to_string_lossy().into_owned();
+                    let value = child
+                        .attr_value(gimli::DW_AT_const_value)?
+                        .and_then(|attr| attr.sdata_value().or(attr.udata_value().map(|v| v as i64)))
+                        .unwrap_or(0);
+                    enumerators.push((enumerator, value));
+                }
+                Self {
+                    name: format!("enum {}", name.unwrap_or_default()),
+                    size,
+                    kind: TypeKind::Enum(enumerators),
+                }
+            }
+            gimli::DW_TAG_structure_type => Self {
+                name: format!("struct {}", name.unwrap_or_default()),
+                size,
+                kind: TypeKind::Aggregate,
+            },
+            gimli::DW_TAG_union_type => Self {
+                name: format!("union {}", name.unwrap_or_default()),
+                size,
+                kind: TypeKind::Aggregate,
+            },
+            gimli::DW_TAG_array_type => {
+                let element = Self::of_entry(dwarf, unit, &entry)?;
+                let count = array_count(unit, offset)?;
+                let size = match count {
+                    Some(count) if size == 0 => count * element.size,
+                    _ => size,
+                };
+                let count = count.map(|c| c.to_string()).unwrap_or_default();
+                Self {
+                    name: format!("{}[{count}]", element.name),
+                    size,
+                    kind: TypeKind::Aggregate,
+                }
+            }
+            gimli::DW_TAG_subroutine_type => Self {
+                name: "<function>".to_string(),
+                size,
+                kind: TypeKind::Aggregate,
+            },
+            _ => Self {
+                name: name.unwrap_or_else(|| format!("<{}>", entry.tag())),
+                size,
+                kind: TypeKind::Aggregate,
+            },
+        };
+
+        Ok(ty)
+    }
+
+    pub fn is_float(&self) -> bool {
+        matches!(self.kind, TypeKind::Float)
+    }
+}
+
+fn array_count(unit: &Unit, offset: gimli::UnitOffset) -> Result<Option<u64>, gimli::Error> {
+    let mut tree = unit.entries_tree(Some(offset))?;
+    let mut children = tree.root()?.children();
+    while let Some(child) = children.next()? {
+        let child = child.entry();
+        if child.tag() != gimli::DW_TAG_subrange_type {
+            continue;
+        }
+        if let Some(count) = child.attr_value(gimli::DW_AT_count)?.and_then(|a| a.udata_value()) {
+            return Ok(Some(count));
+        }
+        if let Some(upper) = child.attr_value(gimli::DW_AT_upper_bound)?.and_then(|a| a.udata_value()) {
+            return Ok(Some(upper + 1));
+        }
+    }
+    Ok(None)
+}
+
+/// The class the System V x86-64 psABI gives an eightbyte of a value, which decides
+/// the kind of register it is passed in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EightbyteClass {
+    Integer,
+    Sse,
+}
+
+/// Where a function returns a value of some type under the System V x86-64 psABI.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReturnLocation {
+    /// Each eightbyte in the next free register of its class: RAX then RDX, or XMM0
+    /// then XMM1. `None` for eightbytes that are only padding.
+    Registers(Vec<Option<EightbyteClass>>),
+    /// In memory the caller provided, whose address the function returns in RAX.
+    Memory,
+    /// Somewhere we can't read it from, such as the x87 stack `long double` uses.
+    Unsupported,
+}
+
+impl ReturnLocation {
+    /// Where a function returns the type referenced by the `DW_AT_type` attribute of
+    /// its `entry`.
+    pub fn of_entry(
+        dwarf: &Dwarf,
+        unit: &Unit,
+        entry: &gimli::DebuggingInformationEntry<StaticEndianSlice>,
+    ) -> Result<Self, gimli::Error> {
+        let Some(gimli::AttributeValue::UnitRef(offset)) = entry.attr_value(gimli::DW_AT_type)? else {
+            return Ok(Self::Registers(Vec::new()));
+        };
+        let size = TypeInfo::at_offset(dwarf, unit, offset)?.size;
+        if size > 16 {
+            return Ok(Self::Memory);
+        }
+        let mut classes = vec![None; size.div_ceil(8) as usize];
+        match classify(dwarf, unit, offset, 0, &mut classes)? {
+            Some(location) => Ok(location),
+            None => Ok(Self::Registers(classes)),
+        }
+    }
+}
+
+/// Merges the classes of the parts of the type at `offset`, which starts `at` bytes
+/// into the returned value, into `classes`. Returns where the whole value goes
+/// instead if this part rules out registers.
+fn classify(
+    dwarf: &Dwarf,
+    unit: &Unit,
+    offset: gimli::UnitOffset,
+    at: u64,
+    classes: &mut [Option<EightbyteClass>],
+) -> Result<Option<ReturnLocation>, gimli::Error> {
+    let entry = unit.entry(offset)?;
+    let ty = TypeInfo::at_offset(dwarf, unit, offset)?;
+    match entry.tag() {
+        gimli::DW_TAG_typedef
+        | gimli::DW_TAG_const_type
+        | gimli::DW_TAG_volatile_type
+        | gimli::DW_TAG_restrict_type
+        | gimli::DW_TAG_atomic_type => match entry.attr_value(gimli::DW_AT_type)? {
+            Some(gimli::AttributeValue::UnitRef(inner)) => classify(dwarf, unit, inner, at, classes),
+            _ => Ok(Some(ReturnLocation::Unsupported)),
+        },
+        gimli::DW_TAG_structure_type | gimli::DW_TAG_class_type | gimli::DW_TAG_union_type => {
+            let mut tree = unit.entries_tree(Some(offset))?;
+            let mut children = tree.root()?.children();
+            while let Some(child) = children.next()? {
+                let member = child.entry();
+                if member.tag() != gimli::DW_TAG_member {
+                    continue;
+                }
+                let member_offset = member
+                    .attr_value(gimli::DW_AT_data_member_location)?
+                    .and_then(|attr| attr.udata_value())
+                    .unwrap_or(0);
+                // Bit fields are always integers, found by their bit offset
+                if member.attr_value(gimli::DW_AT_bit_size)?.is_some() {
+                    let bit_offset = member
+                        .attr_value(gimli::DW_AT_data_bit_offset)?
+                        .and_then(|attr| attr.udata_value())
+                        .unwrap_or(member_offset * 8);
+                    merge(classes, at + bit_offset / 8, 1, EightbyteClass::Integer);
+                    continue;
+                }
+                let Some(gimli::AttributeValue::UnitRef(member_type)) =
+                    member.attr_value(gimli::DW_AT_type)?
+                else {
+                    continue;
+                };
+                if let Some(location) = classify(dwarf, unit, member_type, at + member_offset, classes)? {
+                    return Ok(Some(location));
+                }
+            }
+            Ok(None)
+        }
+        gimli::DW_TAG_array_type => {
+            let Some(gimli::AttributeValue::UnitRef(element)) = entry.attr_value(gimli::DW_AT_type)?
+            else {
+                return Ok(Some(ReturnLocation::Unsupported));
+            };
+            let element_size = TypeInfo::at_offset(dwarf, unit, element)?.size;
+            let count = array_count(unit, offset)?.unwrap_or(0);
+            for index in 0..count {
+                if let Some(location) = classify(dwarf, unit, element, at + index * element_size, classes)? {
+                    return Ok(Some(location));
+                }
+            }
+            Ok(None)
+        }
+        _ => match ty.kind {
+            // `long double` is returned on the x87 stack
+            TypeKind::Float if ty.size > 8 => Ok(Some(ReturnLocation::Unsupported)),
+            TypeKind::Float => {
+                merge(classes, at, ty.size, EightbyteClass::Sse);
+                Ok(None)
+            }
+            TypeKind::Aggregate | TypeKind::Void => Ok(Some(ReturnLocation::Unsupported)),
+            _ => {
+                merge(classes, at, ty.size, EightbyteClass::Integer);
+                Ok(None)
+            }
+        },
+    }
+}
+
+/// Gives the eightbytes the `size` bytes `at` bytes into a value cover the class
+/// `class`. An eightbyte holding both an integer and a float is an integer.
+fn merge(classes: &mut [Option<EightbyteClass>], at: u64, size: u64, class: EightbyteClass) {
+    let eightbytes = (at / 8) as usize..=((at + size.max(1) - 1) / 8) as usize;
+    for slot in classes.iter_mut().take(*eightbytes.end() + 1).skip(*eightbytes.start()) {
+        if *slot != Some(EightbyteClass::Integer) {
+            *slot = Some(class);
+        }
+    }
+}
+
+/// The bytes of a value together with the type they should be read as.
+#[derive(Debug, Clone)]
+pub struct TypedValue {
+    pub ty: TypeInfo,
+    pub bytes: Vec<u8>,
+}
+
+impl TypedValue {
+    fn as_u64(&self) -> u64 {
+        let mut buf = [0u8; 8];
+        let len = self.bytes.len().min(8);
+        buf[..len].copy_from_slice(&self.bytes[..len]);
+        u64::from_le_bytes(buf)
+    }
+
+    fn as_i64(&self) -> i64 {
+        let bits = (self.bytes.len().min(8) * 8) as u32;
+        let value = self.as_u64();
+        if bits == 0 || bits == 64 {
+            return value as i64;
+        }
+        // Sign extend from the width of the value
+        let shift = 64 - bits;
+        ((value << shift) as i64) >> shift
+    }
+
+    /// The value as an integer, for expressions: signed types are sign extended and
+    /// floats truncated.
+    pub fn to_i64(&self) -> i64 {
+        match &self.ty.kind {
+            TypeKind::Signed | TypeKind::Char | TypeKind::Enum(_) => self.as_i64(),
+            TypeKind::Float if self.bytes.len() == 4 => f32::from_bits(self.as_u64() as u32) as i64,
+            TypeKind::Float => f64::from_bits(self.as_u64()) as i64,
+            _ => self.as_u64() as i64,
+        }
+    }
+}
+
+impl Display for TypedValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.ty.kind {
+            TypeKind::Void => write!(f, "void"),
+            TypeKind::Signed => write!(f, "{}", self.as_i64()),
+            TypeKind::Unsigned => write!(f, "{}", self.as_u64()),
+            TypeKind::Bool => write!(f, "{}", self.as_u64() != 0),
+            TypeKind::Char => match char::from_u32(self.as_u64() as u32) {
+                Some(c) => write!(f, "{} {:?}", self.as_i64(), c),
+                None => write!(f, "{}", self.as_i64()),
+            },
+            TypeKind::Float if self.bytes.len() == 4 => {
+                write!(f, "{}", f32::from_bits(self.as_u64() as u32))
+            }
+            TypeKind::Float => write!(f, "{}", f64::from_bits(self.as_u64())),
+            TypeKind::Pointer => write!(f, "0x{:x}", self.as_u64()),
+            TypeKind::Enum(enumerators) => {
+                let value = self.as_i64();
+                match enumerators.iter().find(|(_, v)| *v == value) {
+                    Some((name, _)) => write!(f, "{name}"),
+                    None => write!(f, "{value}"),
+                }
+            }
+            TypeKind::Aggregate => write!(f, "{:02x?}", self.bytes),
+        }
+    }
+}
@@ -0,0 +1,300 @@
+use std::error::Error;
+use std::ops::Range;
+
+use gimli::{
+    BaseAddresses, CfaRule, DebugFrame, EhFrame, EvaluationResult, RegisterRule, UnwindContext,
+    UnwindSection, UnwindTableRow,
+};
+use object::{Object, ObjectSection};
+
+use super::{Endianness, StaticEndianSlice, EMPTY_ARR};
+use crate::registers::Register;
+
+type Expression = gimli::Expression<StaticEndianSlice>;
+
+/// The deepest stack we will walk, in case a corrupt stack loops.
+const MAX_FRAMES: usize = 1024;
+
+/// DWARF numbers the registers from 0 (RAX) to 16 (the return address, i.e. RIP).
+const NUM_DWARF_REGISTERS: usize = 17;
+
+/// The registers the System V x86-64 psABI has a function preserve for its caller.
+const CALLEE_SAVED: [Register; 6] = [
+    Register::RBX,
+    Register::RBP,
+    Register::R12,
+    Register::R13,
+    Register::R14,
+    Register::R15,
+];
+
+/// The call frame information of an ELF file: how to find the caller of the
+/// function at any address. Addresses are before adding the base address.
+pub struct CallFrameInfo {
+    eh_frame: EhFrame<StaticEndianSlice>,
+    debug_frame: DebugFrame<StaticEndianSlice>,
+    bases: BaseAddresses,
+}
+
+impl CallFrameInfo {
+    /// Reads `.eh_frame` and `.debug_frame`, either of which may be missing.
+    pub fn load(elf: &object::File<'static>) -> Self {
+        let endianness = Endianness::from(elf.endianness());
+        let section = |name| {
+            let section = elf.section_by_name(name);
+            let data = section.as_ref().and_then(|section| section.data().ok()).unwrap_or(&EMPTY_ARR);
+            let address = section.map_or(0, |section| section.address());
+            (gimli::EndianSlice::new(data, endianness), address)
+        };
+
+        let (eh_frame, eh_frame_address) = section(".eh_frame");
+        let (debug_frame, _) = section(".debug_frame");
+        let (_, text_address) = section(".text");
+        let mut eh_frame = EhFrame::from(eh_frame);
+        eh_frame.set_address_size(8);
+        let mut debug_frame = DebugFrame::from(debug_frame);
+        debug_frame.set_address_size(8);
+
+        Self {
+            eh_frame,
+            debug_frame,
+            bases: BaseAddresses::default()
+                .set_eh_frame(eh_frame_address)
+                .set_text(text_address),
+        }
+    }
+
+    /// No call frame information at all, for files without any.
+    pub fn empty() -> Self {
+        let eh_frame = gimli::EndianSlice::new(&EMPTY_ARR, Endianness::default());
+        Self {
+            eh_frame: EhFrame::from(eh_frame),
+            debug_frame: DebugFrame::from(eh_frame),
+            bases: BaseAddresses::default(),
+        }
+    }
+
+    /// Reads the call frame information of the ELF file at `filename`, e.g. a shared
+    /// library the program uses.
+    pub fn read(filename: &str) -> Result<Self, Box<dyn Error>> {
+        Ok(Self::load(&super::parse_elf(filename)?))
+    }
+
+    /// The unwind rules in effect at `address`, preferring `.eh_frame`, along with
+    /// the expression that computes the CFA if the rules give it as one.
+    fn row_for_address(&self, address: u64) -> Option<(UnwindTableRow<usize>, Option<Expression>)> {
+        let mut ctx = UnwindContext::new();
+        if let Ok(row) =
+            self.eh_frame
+                .unwind_info_for_address(&self.bases, &mut ctx, address, EhFrame::cie_from_offset)
+        {
+            let expression = cfa_expression(row, &self.eh_frame)?;
+            return Some((row.clone(), expression));
+        }
+        let row = self
+            .debug_frame
+            .unwind_info_for_address(&self.bases, &mut ctx, address, DebugFrame::cie_from_offset)
+            .ok()?;
+        let expression = cfa_expression(row, &self.debug_frame)?;
+        Some((row.clone(), expression))
+    }
+}
+
+/// The expression of `row`'s CFA rule, read from the `section` the row is from.
+/// `Some(None)` if the CFA isn't given by an expression.
+fn cfa_expression(
+    row: &UnwindTableRow<usize>,
+    section: &impl UnwindSection<StaticEndianSlice>,
+) -> Option<Option<Expression>> {
+    match row.cfa() {
+        CfaRule::Expression(expression) => expression.get(section).ok().map(Some),
+        CfaRule::RegisterAndOffset { .. } => Some(None),
+    }
+}
+
+/// The registers of a frame, indexed by DWARF register number. Unwinding can't
+/// recover every register of a caller, so some are unknown.
+#[derive(Debug, Clone)]
+pub struct FrameRegisters([Option<u64>; NUM_DWARF_REGISTERS]);
+
+impl FrameRegisters {
+    /// The registers of the innermost frame, where all of them are known.
+    pub fn from_user_regs(regs: &libc::user_regs_struct) -> Self {
+        let mut registers = [None; NUM_DWARF_REGISTERS];
+        for (number, value) in registers.iter_mut().enumerate() {
+            *value = Register::from_dwarf(number as i32)
+                .map(|reg| *reg.extract_from_reg_struct(regs));
+        }
+        Self(registers)
+    }
+
+    pub fn get(&self, reg: Register) -> Option<u64> {
+        self.by_number(reg.dwarf() as u16)
+    }
+
+    fn by_number(&self, number: u16) -> Option<u64> {
+        self.0.get(number as usize).copied().flatten()
+    }
+
+    fn set(&mut self, reg: Register, value: Option<u64>) {
+        self.0[reg.dwarf() as usize] = value;
+    }
+
+    /// What a caller of this frame is known to have before any unwind rule is
+    /// applied: the callee-saved registers, which the psABI has the callee preserve.
+    /// The caller-saved ones were free for the callee to clobber.
+    fn callee_saved(&self) -> Self {
+        let mut caller = Self([None; NUM_DWARF_REGISTERS]);
+        for reg in CALLEE_SAVED {
+            caller.set(reg, self.get(reg));
+        }
+        caller
+    }
+}
+
+/// The call frame information of an ELF file mapped into the process.
+pub struct Module<'a> {
+    /// The addresses the file is mapped at.
+    pub range: Range<u64>,
+    /// The address the file is loaded at, which its own addresses are relative to.
+    pub base: u64,
+    pub cfi: &'a CallFrameInfo,
+}
+
+/// One frame of the call stack.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub pc: u64,
+    /// The canonical frame address: the stack pointer right before the call that
+    /// made this frame. `None` if it couldn't be worked out.
+    pub cfa: Option<u64>,
+    pub registers: FrameRegisters,
+}
+
+/// Walks the stack outwards from the innermost frame, whose registers are
+/// `registers`. Each caller is found with the CFI rules at the PC, or by following
+/// the `rbp` chain where there are none or they can't be applied. `modules` are the
+/// ELF files whose CFI to use, and `read_word` reads the process's memory.
+pub fn unwind(
+    modules: &[Module],
+    mut registers: FrameRegisters,
+    mut read_word: impl FnMut(u64) -> Option<u64>,
+) -> Vec<Frame> {
+    let mut frames: Vec<Frame> = Vec::new();
+    while frames.len() < MAX_FRAMES {
+        let Some(pc) = registers.get(Register::RIP) else {
+            break;
+        };
+        // A return address is just past the call, which may be past the end of the
+        // calling function when the callee doesn't return
+        let lookup = if frames.is_empty() { pc } else { pc - 1 };
+        let row = modules
+            .iter()
+            .find(|module| module.range.contains(&lookup))
+            .and_then(|module| module.cfi.row_for_address(lookup - module.base));
+        let caller = row
+            .and_then(|(row, cfa)| unwind_with_cfi(&row, cfa, &registers, &mut read_word))
+            .or_else(|| unwind_with_frame_pointer(&registers, &mut read_word));
+
+        let cfa = caller.as_ref().map(|(cfa, _)| *cfa);
+        frames.push(Frame { pc, cfa, registers });
+        let Some((_, caller)) = caller else {
+            break;
+        };
+        // Stop at the outermost frame, or if the stack stops growing towards the caller
+        let sp = frames.last().and_then(|frame| frame.registers.get(Register::RSP));
+        let caller_sp = caller.get(Register::RSP);
+        if caller.get(Register::RIP).is_none_or(|pc| pc == 0) || caller_sp <= sp {
+            break;
+        }
+        registers = caller;
+    }
+    frames
+}
+
+/// Applies the CFI `row` for a frame with `registers`, returning its CFA and its
+/// caller's registers. `cfa_expression` is the expression of the CFA rule, if it
+/// is one.
+fn unwind_with_cfi(
+    row: &UnwindTableRow<usize>,
+    cfa_expression: Option<Expression>,
+    registers: &FrameRegisters,
+    read_word: &mut impl FnMut(u64) -> Option<u64>,
+) -> Option<(u64, FrameRegisters)> {
+    let cfa = match (row.cfa(), cfa_expression) {
+        (CfaRule::RegisterAndOffset { register, offset }, _) => {
+            registers.by_number(register.0)?.wrapping_add_signed(*offset)
+        }
+        (CfaRule::Expression(_), Some(expression)) => {
+            evaluate_cfa(expression, registers, read_word)?
+        }
+        (CfaRule::Expression(_), None) => return None,
+    };
+
+    // Callee-saved registers without a rule keep their value, as the function never
+    // touches them. The caller's stack pointer is the CFA by definition.
+    let mut caller = registers.callee_saved();
+    caller.set(Register::RSP, Some(cfa));
+    for (register, rule) in row.registers() {
+        let Some(slot) = caller.0.get_mut(register.0 as usize) else {
+            continue;
+        };
+        *slot = match rule {
+            RegisterRule::Undefined => None,
+            RegisterRule::SameValue => registers.by_number(register.0),
+            RegisterRule::Offset(offset) => read_word(cfa.wrapping_add_signed(*offset)),
+            RegisterRule::ValOffset(offset) => Some(cfa.wrapping_add_signed(*offset)),
+            RegisterRule::Register(other) => registers.by_number(other.0),
+            RegisterRule::Constant(value) => Some(*value),
+            _ => None,
+        };
+    }
+    Some((cfa, caller))
+}
+
+/// Evaluates a CFA given by `expression`, such as the one PLT stubs use, in a frame
+/// with `registers`.
+fn evaluate_cfa(
+    expression: Expression,
+    registers: &FrameRegisters,
+    read_word: &mut impl FnMut(u64) -> Option<u64>,
+) -> Option<u64> {
+    let encoding = gimli::Encoding { format: gimli::Format::Dwarf32, version: 4, address_size: 8 };
+    let mut evaluation = expression.evaluation(encoding);
+    let mut result = evaluation.evaluate().ok()?;
+    loop {
+        result = match result {
+            EvaluationResult::Complete => break,
+            EvaluationResult::RequiresRegister { register, .. } => {
+                let value = registers.by_number(register.0)?;
+                evaluation.resume_with_register(gimli::Value::Generic(value)).ok()?
+            }
+            EvaluationResult::RequiresMemory { address, size: 8, .. } => {
+                let value = read_word(address)?;
+                evaluation.resume_with_memory(gimli::Value::Generic(value)).ok()?
+            }
+            _ => return None,
+        };
+    }
+    match evaluation.result().as_slice() {
+        [gimli::Piece { location: gimli::Location::Address { address }, .. }] => Some(*address),
+        _ => None,
+    }
+}
+
+/// Finds the caller of a frame with `registers` from the saved `rbp` and return
+/// address a standard prologue leaves at `rbp`, returning the CFA and the caller's
+/// registers.
+fn unwind_with_frame_pointer(
+    registers: &FrameRegisters,
+    read_word: &mut impl FnMut(u64) -> Option<u64>,
+) -> Option<(u64, FrameRegisters)> {
+    let rbp = registers.get(Register::RBP).filter(|&rbp| rbp != 0)?;
+    let cfa = rbp + 16;
+
+    let mut caller = registers.callee_saved();
+    caller.set(Register::RBP, Some(read_word(rbp)?));
+    caller.set(Register::RIP, Some(read_word(rbp + 8)?));
+    caller.set(Register::RSP, Some(cfa));
+    Some((cfa, caller))
+}
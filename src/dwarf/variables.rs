@@ -0,0 +1,311 @@
+use super::types::TypeInfo;
+use super::{die_contains, functions_containing, Dwarf, StaticEndianSlice};
+
+type Unit = gimli::Unit<StaticEndianSlice>;
+type Expression = gimli::Expression<StaticEndianSlice>;
+
+/// A local variable or argument of a function, with where to find its value.
+#[derive(Debug, Clone)]
+pub struct Variable {
+    pub name: String,
+    pub ty: TypeInfo,
+    /// Whether this is a `DW_TAG_formal_parameter` rather than a `DW_TAG_variable`.
+    pub is_argument: bool,
+    pub location: VariableLocation,
+    /// The `DW_AT_frame_base` of the subprogram at the PC, which `DW_OP_fbreg` is
+    /// relative to.
+    pub frame_base: Option<Expression>,
+    pub encoding: gimli::Encoding,
+    /// The unit the variable is in, which typed operations refer to base types of.
+    pub unit: gimli::DebugInfoOffset,
+}
+
+/// Where the value of a variable is at a PC.
+#[derive(Debug, Clone)]
+pub enum VariableLocation {
+    /// The location description in effect at the PC.
+    Expression(Expression),
+    /// The value itself, from `DW_AT_const_value`, as little endian bytes.
+    Constant(Vec<u8>),
+    /// The compiler didn't keep the variable around at the PC.
+    OptimizedOut,
+}
+
+/// What the caller passed in a register at a call, from the
+/// `DW_TAG_call_site_parameter` the compiler left for it.
+#[derive(Debug, Clone)]
+pub struct CallSiteValue {
+    /// Computes the value in the caller's frame after the call returns.
+    pub value: Expression,
+    /// The caller's `DW_AT_frame_base`.
+    pub frame_base: Option<Expression>,
+    pub encoding: gimli::Encoding,
+    /// The unit the call site is in.
+    pub unit: gimli::DebugInfoOffset,
+}
+
+/// The variables and arguments in scope at `pc`, innermost scope first. They are
+/// those of the function `inline_depth` calls out from the innermost function
+/// inlined at `pc`, where 0 is the innermost and the subprogram is the last.
+/// Lexical blocks that don't contain `pc` are skipped, hiding what is out of scope.
+pub fn find_variables_at_pc(
+    dwarf: &Dwarf,
+    pc: u64,
+    base: u64,
+    inline_depth: usize,
+) -> Result<Vec<Variable>, gimli::Error> {
+    let address = pc.wrapping_sub(base);
+    let mut units = dwarf.units();
+
+    while let Some(header) = units.next()? {
+        let unit: Unit = dwarf.unit(header)?;
+        let chain = functions_containing(dwarf, &unit, address)?;
+        let Some(&subprogram) = chain.first() else {
+            continue;
+        };
+        let Some(&function) = chain.iter().rev().nth(inline_depth) else {
+            return Ok(Vec::new());
+        };
+
+        // Inlined functions have no frame of their own, so they share the subprogram's
+        let frame_base = match unit.entry(subprogram)?.attr_value(gimli::DW_AT_frame_base)? {
+            Some(attr) => location_at(dwarf, &unit, attr, address)?,
+            None => None,
+        };
+        let mut tree = unit.entries_tree(Some(function))?;
+        return collect_variables(dwarf, &unit, tree.root()?, address, frame_base);
+    }
+
+    Ok(Vec::new())
+}
+
+/// The variables declared directly in `node` and in its lexical blocks containing
+/// `address`, the innermost blocks first.
+fn collect_variables(
+    dwarf: &Dwarf,
+    unit: &Unit,
+    node: gimli::EntriesTreeNode<StaticEndianSlice>,
+    address: u64,
+    frame_base: Option<Expression>,
+) -> Result<Vec<Variable>, gimli::Error> {
+    let mut variables = Vec::new();
+    let mut nested = Vec::new();
+    let mut children = node.children();
+    while let Some(child) = children.next()? {
+        let entry = child.entry();
+        match entry.tag() {
+            gimli::DW_TAG_variable | gimli::DW_TAG_formal_parameter => {
+                variables.extend(variable_from_entry(dwarf, unit, entry, address, frame_base)?);
+            }
+            gimli::DW_TAG_lexical_block if die_contains(dwarf, unit, entry, address)? => {
+                nested.extend(collect_variables(dwarf, unit, child, address, frame_base)?);
+            }
+            // Nested functions and inlined calls have variables of their own
+            _ => {}
+        }
+    }
+    nested.extend(variables);
+    Ok(nested)
+}
+
+fn variable_from_entry(
+    dwarf: &Dwarf,
+    unit: &Unit,
+    entry: &gimli::DebuggingInformationEntry<StaticEndianSlice>,
+    address: u64,
+    frame_base: Option<Expression>,
+) -> Result<Option<Variable>, gimli::Error> {
+    // Concrete copies of inlined functions keep the name and type in the abstract origin
+    let origin = match entry.attr_value(gimli::DW_AT_abstract_origin)? {
+        Some(gimli::AttributeValue::UnitRef(offset)) => unit.entry(offset)?,
+        _ => entry.clone(),
+    };
+    let Some(name) = entry
+        .attr_value(gimli::DW_AT_name)?
+        .or(origin.attr_value(gimli::DW_AT_name)?)
+    else {
+        return Ok(None);
+    };
+    let ty = match entry.attr_value(gimli::DW_AT_type)? {
+        Some(_) => TypeInfo::of_entry(dwarf, unit, entry)?,
+        None => TypeInfo::of_entry(dwarf, unit, &origin)?,
+    };
+    let location = match (
+        entry.attr_value(gimli::DW_AT_location)?,
+        entry.attr_value(gimli::DW_AT_const_value)?,
+    ) {
+        (Some(attr), _) => match location_at(dwarf, unit, attr, address)? {
+            Some(expression) => VariableLocation::Expression(expression),
+            None => VariableLocation::OptimizedOut,
+        },
+        (None, Some(value)) => VariableLocation::Constant(constant_bytes(value)),
+        (None, None) => VariableLocation::OptimizedOut,
+    };
+
+    Ok(Some(Variable {
+        name: dwarf.attr_string(unit, name)?.to_string_lossy().into_owned(),
+        ty,
+        is_argument: entry.tag() == gimli::DW_TAG_formal_parameter,
+        location,
+        frame_base,
+        encoding: unit.encoding(),
+        unit: unit_offset(unit),
+    }))
+}
+
+/// The offset of `unit` in `.debug_info`.
+fn unit_offset(unit: &Unit) -> gimli::DebugInfoOffset {
+    unit.header.offset().as_debug_info_offset().unwrap_or(gimli::DebugInfoOffset(0))
+}
+
+/// The type of the `DW_TAG_base_type` at `offset` in the unit at `unit`, for typed
+/// DWARF operations. `None` if it isn't a base type they can use.
+pub fn base_value_type(
+    dwarf: &Dwarf,
+    unit: gimli::DebugInfoOffset,
+    offset: gimli::UnitOffset,
+) -> Result<Option<gimli::ValueType>, gimli::Error> {
+    let unit: Unit = dwarf.unit(dwarf.debug_info.header_from_offset(unit)?)?;
+    gimli::ValueType::from_entry(&unit.entry(offset)?)
+}
+
+/// The location description of `attr` in effect at `address`: the expression
+/// itself, or the entry of a location list covering `address`. `None` when no entry
+/// does, meaning the value isn't available there.
+fn location_at(
+    dwarf: &Dwarf,
+    unit: &Unit,
+    attr: gimli::AttributeValue<StaticEndianSlice>,
+    address: u64,
+) -> Result<Option<Expression>, gimli::Error> {
+    if let gimli::AttributeValue::Exprloc(expression) = attr {
+        return Ok(Some(expression));
+    }
+    let Some(mut locations) = dwarf.attr_locations(unit, attr)? else {
+        return Ok(None);
+    };
+    while let Some(entry) = locations.next()? {
+        if (entry.range.begin..entry.range.end).contains(&address) {
+            return Ok(Some(entry.data));
+        }
+    }
+    Ok(None)
+}
+
+/// The bytes of a `DW_AT_const_value`.
+fn constant_bytes(value: gimli::AttributeValue<StaticEndianSlice>) -> Vec<u8> {
+    match value {
+        gimli::AttributeValue::Block(block) => block.slice().to_vec(),
+        gimli::AttributeValue::Data1(value) => vec![value],
+        gimli::AttributeValue::Data2(value) => value.to_le_bytes().to_vec(),
+        gimli::AttributeValue::Data4(value) => value.to_le_bytes().to_vec(),
+        gimli::AttributeValue::Sdata(value) => value.to_le_bytes().to_vec(),
+        value => value.udata_value().unwrap_or(0).to_le_bytes().to_vec(),
+    }
+}
+
+/// How to work out what was passed in `register` at the call that returns to
+/// `return_address`, if the compiler described it. The address is before adding
+/// the base.
+pub fn find_call_site_value(
+    dwarf: &Dwarf,
+    return_address: u64,
+    register: gimli::Register,
+) -> Result<Option<CallSiteValue>, gimli::Error> {
+    // The return address can be right past the end of a call that doesn't return
+    let Some(call_address) = return_address.checked_sub(1) else {
+        return Ok(None);
+    };
+    let mut units = dwarf.units();
+
+    while let Some(header) = units.next()? {
+        let unit: Unit = dwarf.unit(header)?;
+        let chain = functions_containing(dwarf, &unit, call_address)?;
+        let Some(&subprogram) = chain.first() else {
+            continue;
+        };
+        let frame_base = match unit.entry(subprogram)?.attr_value(gimli::DW_AT_frame_base)? {
+            Some(attr) => location_at(dwarf, &unit, attr, call_address)?,
+            None => None,
+        };
+
+        let mut entries = unit.entries_at_offset(subprogram)?;
+        entries.next_dfs()?;
+        let mut depth = 0;
+        while let Some((delta, entry)) = entries.next_dfs()? {
+            depth += delta;
+            if depth <= 0 {
+                break;
+            }
+            // GCC's DWARF 4 extension calls the return address the low PC
+            let return_pc = match entry.tag() {
+                gimli::DW_TAG_call_site => entry.attr_value(gimli::DW_AT_call_return_pc)?,
+                gimli::DW_TAG_GNU_call_site => entry.attr_value(gimli::DW_AT_low_pc)?,
+                _ => continue,
+            };
+            match return_pc {
+                Some(attr) if dwarf.attr_address(&unit, attr)? == Some(return_address) => {}
+                _ => continue,
+            }
+
+            let mut tree = unit.entries_tree(Some(entry.offset()))?;
+            let mut parameters = tree.root()?.children();
+            while let Some(parameter) = parameters.next()? {
+                let parameter = parameter.entry();
+                let Some(gimli::AttributeValue::Exprloc(location)) =
+                    parameter.attr_value(gimli::DW_AT_location)?
+                else {
+                    continue;
+                };
+                let mut operations = location.operations(unit.encoding());
+                if operations.next()? != Some(gimli::Operation::Register { register }) {
+                    continue;
+                }
+                let value = match parameter.attr_value(gimli::DW_AT_call_value)? {
+                    Some(value) => Some(value),
+                    None => parameter.attr_value(gimli::DW_AT_GNU_call_site_value)?,
+                };
+                if let Some(gimli::AttributeValue::Exprloc(value)) = value {
+                    return Ok(Some(CallSiteValue {
+                        value,
+                        frame_base,
+                        encoding: unit.encoding(),
+                        unit: unit_offset(&unit),
+                    }));
+                }
+            }
+            // The call site doesn't say what was passed in `register`
+            return Ok(None);
+        }
+        // Other units can cover the same addresses
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dwarf::Endianness;
+    use gimli::AttributeValue;
+
+    #[test]
+    fn constants_are_little_endian() {
+        assert_eq!(constant_bytes(AttributeValue::Data1(0xab)), [0xab]);
+        assert_eq!(constant_bytes(AttributeValue::Data2(0x1234)), [0x34, 0x12]);
+        assert_eq!(constant_bytes(AttributeValue::Data4(0x1234_5678)), [0x78, 0x56, 0x34, 0x12]);
+        assert_eq!(constant_bytes(AttributeValue::Data8(1)), [1, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(constant_bytes(AttributeValue::Udata(300)), [0x2c, 1, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn signed_constants_are_sign_extended() {
+        assert_eq!(constant_bytes(AttributeValue::Sdata(-2)), [0xfe, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+    }
+
+    #[test]
+    fn block_constants_are_copied() {
+        let block = gimli::EndianSlice::new(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12], Endianness::Little);
+        assert_eq!(constant_bytes(AttributeValue::Block(block)), [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]);
+    }
+}
@@ -0,0 +1,428 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
+use crate::ptrace;
+use crate::registers::Register;
+use crate::target::Target;
+
+/// A small C-like expression used for breakpoint conditions, e.g.
+/// `rdi == 4 && (i32)*(rbp - 4) != 0`. Every value is an `i64`, `*` reads a whole
+/// word, and casts like `(u8)` truncate to narrower integers. Comparisons and
+/// logical operators produce `0` or `1`. Other names are variables in scope in the
+/// selected frame.
+#[derive(Debug, Clone)]
+pub struct Expr {
+    source: String,
+    node: Node,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Literal(i64),
+    Register(Register),
+    Identifier(String),
+    Deref(Box<Node>),
+    Cast(IntType, Box<Node>),
+    Unary(UnaryOp, Box<Node>),
+    Binary(BinaryOp, Box<Node>, Box<Node>),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum IntType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    I64,
+    U64,
+}
+
+impl IntType {
+    fn from_name(name: &str) -> Option<Self> {
+        let ty = match name {
+            "i8" => Self::I8,
+            "u8" => Self::U8,
+            "i16" => Self::I16,
+            "u16" => Self::U16,
+            "i32" => Self::I32,
+            "u32" => Self::U32,
+            "i64" => Self::I64,
+            "u64" => Self::U64,
+            _ => return None,
+        };
+        Some(ty)
+    }
+
+    fn cast(self, value: i64) -> i64 {
+        match self {
+            Self::I8 => value as i8 as i64,
+            Self::U8 => value as u8 as i64,
+            Self::I16 => value as i16 as i64,
+            Self::U16 => value as u16 as i64,
+            Self::I32 => value as i32 as i64,
+            Self::U32 => value as u32 as i64,
+            Self::I64 | Self::U64 => value,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum UnaryOp {
+    Neg,
+    Not,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum BinaryOp {
+    Add,
+    Sub,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(i64),
+    Ident(String),
+    Op(&'static str),
+}
+
+impl Expr {
+    pub fn evaluate(&self, target: &mut Target) -> Result<i64, Box<dyn std::error::Error>> {
+        self.node.evaluate(target)
+    }
+}
+
+impl Node {
+    fn evaluate(&self, target: &mut Target) -> Result<i64, Box<dyn std::error::Error>> {
+        let value = match self {
+            Node::Literal(value) => *value,
+            Node::Register(reg) => target.frame_register(*reg)? as i64,
+            Node::Identifier(name) => match target.variable_value(name)? {
+                Some(value) => value.to_i64(),
+                None => return Err(format!("No symbol \"{name}\" in current context").into()),
+            },
+            Node::Deref(addr) => {
+                let addr = addr.evaluate(target)? as u64;
+                ptrace::peekdata_as::<i64>(target.pid(), addr)?
+            }
+            Node::Cast(ty, operand) => ty.cast(operand.evaluate(target)?),
+            Node::Unary(UnaryOp::Neg, operand) => operand.evaluate(target)?.wrapping_neg(),
+            Node::Unary(UnaryOp::Not, operand) => (operand.evaluate(target)? == 0) as i64,
+            // Short circuit so e.g. `rdi != 0 && *rdi == 1` doesn't read address 0
+            Node::Binary(BinaryOp::And, lhs, rhs) => {
+                (lhs.evaluate(target)? != 0 && rhs.evaluate(target)? != 0) as i64
+            }
+            Node::Binary(BinaryOp::Or, lhs, rhs) => {
+                (lhs.evaluate(target)? != 0 || rhs.evaluate(target)? != 0) as i64
+            }
+            Node::Binary(op, lhs, rhs) => {
+                let (lhs, rhs) = (lhs.evaluate(target)?, rhs.evaluate(target)?);
+                match op {
+                    BinaryOp::Add => lhs.wrapping_add(rhs),
+                    BinaryOp::Sub => lhs.wrapping_sub(rhs),
+                    BinaryOp::Eq => (lhs == rhs) as i64,
+                    BinaryOp::Ne => (lhs != rhs) as i64,
+                    BinaryOp::Lt => (lhs < rhs) as i64,
+                    BinaryOp::Le => (lhs <= rhs) as i64,
+                    BinaryOp::Gt => (lhs > rhs) as i64,
+                    BinaryOp::Ge => (lhs >= rhs) as i64,
+                    BinaryOp::And | BinaryOp::Or => unreachable!("Handled above"),
+                }
+            }
+        };
+        Ok(value)
+    }
+}
+
+impl FromStr for Expr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(s)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let node = parser.parse_or()?;
+        if let Some(token) = parser.peek() {
+            return Err(format!("Unexpected {token:?} in expression"));
+        }
+
+        Ok(Self {
+            source: s.trim().to_string(),
+            node,
+        })
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+/// Longer operators come first so `<=` isn't read as `<` followed by `=`.
+const OPERATORS: [&str; 15] = [
+    "==", "!=", "<=", ">=", "&&", "||", "<", ">", "!", "-", "+", "*", "(", ")", "=",
+];
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = s.trim_start();
+
+    while let Some(c) = rest.chars().next() {
+        if c.is_ascii_digit() {
+            let end = rest.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(rest.len());
+            let literal = &rest[..end];
+            let value = match literal.strip_prefix("0x") {
+                Some(hex) => u64::from_str_radix(hex, 16).map(|v| v as i64),
+                None => literal.parse(),
+            };
+            tokens.push(Token::Number(value.map_err(|_| format!("Invalid number {literal}"))?));
+            rest = &rest[end..];
+        } else if c.is_ascii_alphabetic() || c == '_' || c == '$' {
+            let end = rest[1..]
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .map_or(rest.len(), |end| end + 1);
+            tokens.push(Token::Ident(rest[..end].to_string()));
+            rest = &rest[end..];
+        } else {
+            let op = OPERATORS
+                .iter()
+                .find(|op| rest.starts_with(**op))
+                .ok_or_else(|| format!("Unexpected character '{c}' in expression"))?;
+            if *op == "=" {
+                return Err("Use == to compare".to_string());
+            }
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        }
+        rest = rest.trim_start();
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn eat(&mut self, op: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Op(next)) if *next == op) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn parse_or(&mut self) -> Result<Node, String> {
+        let mut lhs = self.parse_and()?;
+        while self.eat("||") {
+            lhs = Node::Binary(BinaryOp::Or, Box::new(lhs), Box::new(self.parse_and()?));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_and(&mut self) -> Result<Node, String> {
+        let mut lhs = self.parse_comparison()?;
+        while self.eat("&&") {
+            lhs = Node::Binary(BinaryOp::And, Box::new(lhs), Box::new(self.parse_comparison()?));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_comparison(&mut self) -> Result<Node, String> {
+        let lhs = self.parse_additive()?;
+        let op = match self.peek() {
+            Some(Token::Op("==")) => BinaryOp::Eq,
+            Some(Token::Op("!=")) => BinaryOp::Ne,
+            Some(Token::Op("<")) => BinaryOp::Lt,
+            Some(Token::Op("<=")) => BinaryOp::Le,
+            Some(Token::Op(">")) => BinaryOp::Gt,
+            Some(Token::Op(">=")) => BinaryOp::Ge,
+            _ => return Ok(lhs),
+        };
+        self.pos += 1;
+        Ok(Node::Binary(op, Box::new(lhs), Box::new(self.parse_additive()?)))
+    }
+
+    fn parse_additive(&mut self) -> Result<Node, String> {
+        let mut lhs = self.parse_unary()?;
+        loop {
+            let op = if self.eat("+") {
+                BinaryOp::Add
+            } else if self.eat("-") {
+                BinaryOp::Sub
+            } else {
+                return Ok(lhs);
+            };
+            lhs = Node::Binary(op, Box::new(lhs), Box::new(self.parse_unary()?));
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Node, String> {
+        if self.eat("*") {
+            return Ok(Node::Deref(Box::new(self.parse_unary()?)));
+        }
+        if self.eat("-") {
+            return Ok(Node::Unary(UnaryOp::Neg, Box::new(self.parse_unary()?)));
+        }
+        if self.eat("!") {
+            return Ok(Node::Unary(UnaryOp::Not, Box::new(self.parse_unary()?)));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Node, String> {
+        match self.next() {
+            Some(Token::Number(value)) => Ok(Node::Literal(value)),
+            Some(Token::Ident(name)) => {
+                let register_name = name.strip_prefix('$').unwrap_or(&name);
+                match Register::from_str(register_name.to_uppercase().as_str()) {
+                    Ok(reg) => Ok(Node::Register(reg)),
+                    Err(_) => Ok(Node::Identifier(name)),
+                }
+            }
+            Some(Token::Op("(")) => {
+                if let Some(Token::Ident(name)) = self.peek() {
+                    if let Some(ty) = IntType::from_name(name) {
+                        self.pos += 1;
+                        if !self.eat(")") {
+                            return Err("Expected ) after cast".to_string());
+                        }
+                        return Ok(Node::Cast(ty, Box::new(self.parse_unary()?)));
+                    }
+                }
+                let node = self.parse_or()?;
+                if !self.eat(")") {
+                    return Err("Expected )".to_string());
+                }
+                Ok(node)
+            }
+            Some(token) => Err(format!("Unexpected {token:?} in expression")),
+            None => Err("Unexpected end of expression".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Node {
+        s.parse::<Expr>().unwrap().node
+    }
+
+    fn parse_err(s: &str) -> String {
+        s.parse::<Expr>().unwrap_err()
+    }
+
+    #[test]
+    fn tokenizes_longest_operator_first() {
+        let tokens = tokenize("a<=0x10").unwrap();
+        assert_eq!(
+            tokens,
+            [Token::Ident("a".to_string()), Token::Op("<="), Token::Number(16)]
+        );
+    }
+
+    #[test]
+    fn registers_and_variables() {
+        assert!(matches!(parse("rdi"), Node::Register(Register::RDI)));
+        assert!(matches!(parse("$RAX"), Node::Register(Register::RAX)));
+        assert!(matches!(parse("count"), Node::Identifier(name) if name == "count"));
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let node = parse("a || b && c");
+        let Node::Binary(BinaryOp::Or, _, rhs) = node else {
+            panic!("Expected ||, got {node:?}");
+        };
+        assert!(matches!(*rhs, Node::Binary(BinaryOp::And, _, _)));
+    }
+
+    #[test]
+    fn subtraction_is_left_associative() {
+        let node = parse("10 - 4 - 3");
+        let Node::Binary(BinaryOp::Sub, lhs, rhs) = node else {
+            panic!("Expected -, got {node:?}");
+        };
+        assert!(matches!(*lhs, Node::Binary(BinaryOp::Sub, _, _)));
+        assert!(matches!(*rhs, Node::Literal(3)));
+    }
+
+    #[test]
+    fn cast_applies_to_unary_operand() {
+        let node = parse("(i32)*(rbp - 4) != 0");
+        let Node::Binary(BinaryOp::Ne, lhs, _) = node else {
+            panic!("Expected !=, got {node:?}");
+        };
+        let Node::Cast(IntType::I32, operand) = *lhs else {
+            panic!("Expected cast, got {lhs:?}");
+        };
+        assert!(matches!(*operand, Node::Deref(_)));
+    }
+
+    #[test]
+    fn parenthesised_names_are_not_casts() {
+        assert!(matches!(parse("(x)"), Node::Identifier(name) if name == "x"));
+    }
+
+    #[test]
+    fn source_is_trimmed() {
+        assert_eq!("  rdi == 4 ".parse::<Expr>().unwrap().to_string(), "rdi == 4");
+    }
+
+    #[test]
+    fn casts_truncate() {
+        assert_eq!(IntType::U8.cast(0x1ff), 0xff);
+        assert_eq!(IntType::I8.cast(0xff), -1);
+        assert_eq!(IntType::U32.cast(-1), 0xffff_ffff);
+    }
+
+    #[test]
+    fn single_equals_is_rejected() {
+        assert_eq!(parse_err("rdi = 4"), "Use == to compare");
+    }
+
+    #[test]
+    fn bad_tokens_are_rejected() {
+        assert_eq!(parse_err("rdi % 2"), "Unexpected character '%' in expression");
+        assert_eq!(parse_err("0xzz"), "Invalid number 0xzz");
+        assert_eq!(parse_err("12ab"), "Invalid number 12ab");
+    }
+
+    #[test]
+    fn incomplete_expressions_are_rejected() {
+        assert_eq!(parse_err(""), "Unexpected end of expression");
+        assert_eq!(parse_err("rdi =="), "Unexpected end of expression");
+        assert_eq!(parse_err("(rdi + 1"), "Expected )");
+        assert_eq!(parse_err("(u8 rdi"), "Expected ) after cast");
+    }
+
+    #[test]
+    fn trailing_tokens_are_rejected() {
+        assert_eq!(parse_err("1 2"), "Unexpected Number(2) in expression");
+        assert_eq!(parse_err("rdi)"), "Unexpected Op(\")\") in expression");
+        assert_eq!(parse_err("1 < 2 < 3"), "Unexpected Op(\"<\") in expression");
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+
+/// How to start the program being debugged, kept across runs so `run` can start
+/// it again the same way.
+#[derive(Debug, Clone)]
+pub struct LaunchSettings {
+    pub program: String,
+    /// The arguments as given, which may include `<`, `>`, `>>`, `2>` and `2>>`
+    /// redirections.
+    pub args: Vec<String>,
+    /// Changes to the environment we were started with. `None` removes the variable.
+    pub env: BTreeMap<String, Option<String>>,
+    /// A terminal to use for the program's stdin, stdout and stderr instead of ours.
+    pub tty: Option<String>,
+    /// Run the program with ASLR off, so its addresses are the same every run.
+    pub disable_randomization: bool,
+}
+
+impl LaunchSettings {
+    pub fn new(program: String, args: Vec<String>) -> Self {
+        Self {
+            program,
+            args,
+            env: BTreeMap::default(),
+            tty: None,
+            disable_randomization: true,
+        }
+    }
+
+    /// The command to start the program with, with its redirections and terminal
+    /// opened and set as its stdio.
+    pub fn command(&self) -> io::Result<Command> {
+        let mut command = Command::new(&self.program);
+        if self.disable_randomization {
+            // Safety: `personality` is async-signal-safe, so it is fine to call
+            // between `fork` and `exec`.
+            unsafe {
+                command.pre_exec(disable_randomization);
+            }
+        }
+        for (name, value) in &self.env {
+            match value {
+                Some(value) => command.env(name, value),
+                None => command.env_remove(name),
+            };
+        }
+
+        if let Some(tty) = &self.tty {
+            let open_tty = || OpenOptions::new().read(true).write(true).open(tty);
+            command.stdin(open_tty()?).stdout(open_tty()?).stderr(open_tty()?);
+        }
+
+        let mut args = self.args.iter();
+        while let Some(arg) = args.next() {
+            let Some((redirect, path)) = Redirect::parse(arg) else {
+                command.arg(arg);
+                continue;
+            };
+            let path = match path {
+                "" => args.next().ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidInput, format!("Missing file after {arg}"))
+                })?,
+                path => path,
+            };
+            let file = redirect.open(path)?;
+            match redirect {
+                Redirect::Stdin => command.stdin(file),
+                Redirect::Stdout { .. } => command.stdout(file),
+                Redirect::Stderr { .. } => command.stderr(file),
+            };
+        }
+        Ok(command)
+    }
+
+    /// Sets a variable from `NAME=VALUE` or `NAME VALUE`. A bare `NAME` sets it
+    /// to the empty string.
+    pub fn set_env(&mut self, assignment: &str) -> Result<(), &'static str> {
+        let (name, value) = assignment
+            .split_once(['=', ' '])
+            .map(|(name, value)| (name.trim(), value.trim()))
+            .unwrap_or((assignment.trim(), ""));
+        if name.is_empty() {
+            return Err("Give an environment variable name");
+        }
+        self.env.insert(name.to_string(), Some(value.to_string()));
+        Ok(())
+    }
+
+    pub fn unset_env(&mut self, name: &str) {
+        self.env.insert(name.to_string(), None);
+    }
+}
+
+/// Turns off ASLR for this process and the program it is about to `exec`.
+fn disable_randomization() -> io::Result<()> {
+    // 0xffffffff queries the current persona without changing it
+    let persona = unsafe { libc::personality(0xffffffff) };
+    if persona == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let persona = persona as libc::c_ulong | libc::ADDR_NO_RANDOMIZE as libc::c_ulong;
+    if unsafe { libc::personality(persona) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// A shell-style redirection in the program arguments.
+#[derive(Debug, Clone, Copy)]
+enum Redirect {
+    Stdin,
+    Stdout { append: bool },
+    Stderr { append: bool },
+}
+
+impl Redirect {
+    /// Splits an argument like `>out.txt` into the redirection and the path
+    /// attached to it, which is empty when the path is the next argument.
+    fn parse(arg: &str) -> Option<(Self, &str)> {
+        // Longer operators come first so `>>` isn't read as `>`
+        let operators = [
+            ("2>>", Self::Stderr { append: true }),
+            ("2>", Self::Stderr { append: false }),
+            (">>", Self::Stdout { append: true }),
+            (">", Self::Stdout { append: false }),
+            ("<", Self::Stdin),
+        ];
+        operators
+            .into_iter()
+            .find_map(|(operator, redirect)| arg.strip_prefix(operator).map(|path| (redirect, path)))
+    }
+
+    fn open(self, path: &str) -> io::Result<Stdio> {
+        let file = match self {
+            Self::Stdin => File::open(path),
+            Self::Stdout { append } | Self::Stderr { append } => OpenOptions::new()
+                .write(true)
+                .create(true)
+                .append(append)
+                .truncate(!append)
+                .open(path),
+        };
+        file.map(Stdio::from)
+            .map_err(|e| io::Error::new(e.kind(), format!("{path}: {e}")))
+    }
+}
+
+impl Display for LaunchSettings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.program)?;
+        for arg in &self.args {
+            write!(f, " {arg}")?;
+        }
+        Ok(())
+    }
+}
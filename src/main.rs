@@ -3,38 +3,66 @@
 use std::{
     error::Error,
     io::{self, Write},
-    process::Command,
     str::FromStr,
 };
 
 pub mod breakpoint;
+pub mod debugreg;
 pub mod dwarf;
+pub mod expr;
+pub mod launch;
 pub mod prelude;
 pub mod ptrace;
 pub mod registers;
+pub mod signal;
+pub mod stop;
+pub mod syscall;
 pub mod target;
+pub mod thread;
 
 pub mod mmap;
 
+use breakpoint::{BreakpointKind, CatchEvent, Location, WatchAccess};
+use dwarf::types::TypeInfo;
+use expr::Expr;
+use launch::LaunchSettings;
 use prelude::*;
-use crate::dwarf::{find_function_at_pc, CodePoint};
+use stop::StopReason;
 
 fn main() -> Result<(), Box<dyn Error>> {
     let mut input = String::new();
-    let target_name = std::env::args().nth(1).unwrap_or_else(|| "./hello".to_string());
+    let mut args = std::env::args().skip(1);
 
-    let mut target = launch_traceable(Command::new(target_name)).unwrap();
-    let child_pid = target.pid();
-    println!("Attaching to program with pid {}", child_pid.0);
+    // llemon (-p <pid> | <program> <args>*)
+    let mut target = match args.next().as_deref() {
+        Some("-p") => {
+            let pid = args.next().ok_or("Expected a pid after -p")?.parse()?;
+            let target = attach_traceable(Pid(pid))?;
+            println!("Attached to process {pid}");
+            target
+        }
+        program => {
+            let program = program.unwrap_or("./hello").to_string();
+            let mut target = launch_traceable(LaunchSettings::new(program, args.collect()))?;
+            println!("Attaching to program with pid {}", target.pid().0);
+            println!("Program entry point is 0x{:x}", target.entry_point()?);
+            print_stop(&mut target, &StopReason::SingleStep)?;
+            target
+        }
+    };
     dwarf::process_dwarf_test::<dwarf::StaticEndianSlice>(&mut target.dwinfo.dwarf).unwrap();
 
     loop {
         print!(">>> ");
         input.clear();
         io::stdout().flush()?;
-        io::stdin()
+        let read = io::stdin()
             .read_line(&mut input)
             .expect("Failed to read line");
+        if read == 0 {
+            // End of input, leave the same way `exit` does
+            input = "exit".to_string();
+        }
 
         if let Err(e) = run_command(&mut target, &input) {
             println!("Error: {e}");
@@ -51,37 +79,392 @@ fn run_command(target: &mut target::Target, line: &str) -> Result<(), Box<dyn Er
     let child_pid = target.pid();
 
     match command {
-        "where" => {
-            let pc = ptrace::get_reg(child_pid, registers::Register::pc())?;
-            let base = target.get_base_address()?;
-            match find_function_at_pc(&target.dwinfo.dwarf, pc, base)? {
-                None => println!("No function found at {pc:x}"),
-                Some(cp) => {
-                    println!("{}", cp);
+        "where" => match target.current_code_point()? {
+            None => {
+                let pc = ptrace::get_reg(target.tid(), registers::Register::pc())?;
+                println!("No function found at {pc:x}");
+            }
+            Some(cp) => {
+                println!("{}", cp);
+                // Show the chain of calls the current function was inlined through, if any
+                let stack = target.stack_frames()?;
+                let inlined = stack.iter().take_while(|frame| frame.real_level == 0).count();
+                if inlined > 1 {
+                    for (level, frame) in stack.iter().take(inlined).enumerate() {
+                        println!("#{level:<3} {}", describe_frame(frame));
+                    }
                 }
             }
         },
+        "backtrace" | "bt" => {
+            // backtrace <count>?
+            let limit = match inp.next() {
+                Some(count) => count.parse()?,
+                None => usize::MAX,
+            };
+            let stack = target.stack_frames()?;
+            for (level, frame) in stack.iter().enumerate().take(limit) {
+                println!("#{level:<3} {}", describe_frame(frame));
+            }
+            if stack.len() > limit {
+                println!("(More stack frames follow...)");
+            }
+        }
+        "frame" | "f" => {
+            // frame <level>?
+            let level = match inp.next() {
+                Some(level) => level.parse()?,
+                None => target.selected_frame(),
+            };
+            let frame = target
+                .select_frame(level)?
+                .ok_or_else(|| format!("No frame at level {level}"))?;
+            println!("#{level:<3} {}", describe_frame(&frame));
+        }
+        "up" | "down" => {
+            // up|down <count>?
+            let count: usize = match inp.next() {
+                Some(count) => count.parse()?,
+                None => 1,
+            };
+            let selected = target.selected_frame();
+            let (level, frame) = match command {
+                "up" => {
+                    let frame = match selected.checked_add(count) {
+                        Some(level) => target.select_frame(level)?.map(|frame| (level, frame)),
+                        None => None,
+                    };
+                    frame.ok_or("Initial frame selected; you cannot go up.")?
+                }
+                _ => {
+                    let level = selected
+                        .checked_sub(count)
+                        .ok_or("Bottom (innermost) frame selected; you cannot go down.")?;
+                    let frame = target.select_frame(level)?;
+                    (level, frame.ok_or("The selected frame no longer exists")?)
+                }
+            };
+            println!("#{level:<3} {}", describe_frame(&frame));
+        }
         "d" => ignore(dbg!(&target)),
-        "continue" | "c" => target.continue_process()?,
-        "stepi" | "si" => target.step_instruction()?,
-        "break" | "b" => {
-            // break <address|function_name>
-            let location = inp.next().ok_or("Give location to add the breakpoint")?;
-            if location.starts_with("0x") {
-                let addr = u64::from_str_radix(location, 16)?;
-                target.add_breakpoint_at(addr)?;
-            }
-            else if location.chars().all(|c| c.is_ascii_digit()) {
-                let addr = u64::from_str_radix(location, 10)?;
-                target.add_breakpoint_at(addr)?;
-            } else {
-                let function_name = location;
-                target.add_breakpoint_at_function(function_name)?;
-                println!("Added breakpoint at function {function_name}");
+        "continue" | "c" => {
+            let reason = target.continue_process()?;
+            print_stop(target, &reason)?;
+        }
+        "stepi" | "si" => {
+            let reason = target.step_instruction()?;
+            print_stop(target, &reason)?;
+        }
+        "step" | "s" => {
+            let reason = target.step_line()?;
+            print_stop(target, &reason)?;
+        }
+        "next" | "n" => {
+            let reason = target.next_line()?;
+            print_stop(target, &reason)?;
+        }
+        "finish" => {
+            let (reason, returned) = target.finish()?;
+            print_stop(target, &reason)?;
+            match returned {
+                Some(Ok(value)) if !value.bytes.is_empty() => {
+                    println!("Value returned: ({}) {}", value.ty.name, value);
+                }
+                Some(Err(e)) => println!("Value returned: <{e}>"),
+                _ => {}
             }
         }
-        "exit" => {
+        "break" | "b" | "hbreak" => {
+            // (h)break <*address|0xaddress|line|file:line|function_name> (if <condition>)?
+            let location: Location = inp
+                .next()
+                .ok_or("Give location to add the breakpoint")?
+                .parse()?;
+            let condition = match inp.next() {
+                Some("if") => Some(inp.collect::<Vec<_>>().join(" ").parse::<Expr>()?),
+                Some(other) => return Err(format!("Expected `if`, got {other}").into()),
+                None => None,
+            };
+            let kind = match command {
+                "hbreak" => BreakpointKind::Hardware,
+                _ => BreakpointKind::Software,
+            };
+            let number = target.add_breakpoint(location, kind)?;
+            target.set_breakpoint_condition(number, condition)?;
+            let breakpoint = &target.user_breakpoints[&number];
+            println!(
+                "{} {number} at {}: {} location(s)",
+                breakpoint.kind,
+                breakpoint.location,
+                breakpoint.addresses.len()
+            );
+        }
+        "watch" | "rwatch" | "awatch" => {
+            // watch <addr>(:<type>)?
+            let addr_and_type = inp.next().ok_or("Give address, optionally give a type")?;
+            let (addr_str, typename) = match addr_and_type.split_once(':') {
+                Some((addr, ty)) => (addr, ty),
+                None => (addr_and_type, "i64"),
+            };
+            let addr = parse_address(addr_str)?;
+            let ty = TypeInfo::primitive(typename)
+                .ok_or_else(|| format!("Invalid typename: {typename}"))?;
+            let access = match command {
+                "watch" => WatchAccess::Write,
+                "rwatch" => WatchAccess::Read,
+                _ => WatchAccess::Access,
+            };
+
+            let number = target.add_watchpoint(addr, ty, access)?;
+            let breakpoint = &target.user_breakpoints[&number];
+            println!("{} {number}: {}", breakpoint.kind, breakpoint.location);
+        }
+        "info" => match inp.next() {
+            Some("breakpoints" | "break" | "b") => {
+                if target.user_breakpoints.is_empty() {
+                    println!("No breakpoints.");
+                }
+                println!(
+                    "{:<4} {:<16} {:<4} {:<6} {:<24} {:<32} Condition",
+                    "Num", "Type", "Enb", "Hits", "Location", "Addresses"
+                );
+                for breakpoint in target.user_breakpoints.values() {
+                    let addresses: Vec<String> =
+                        breakpoint.addresses.iter().map(|addr| format!("0x{addr:x}")).collect();
+                    let condition = breakpoint
+                        .condition
+                        .as_ref()
+                        .map(|condition| condition.to_string())
+                        .unwrap_or_default();
+                    println!(
+                        "{:<4} {:<16} {:<4} {:<6} {:<24} {:<32} {}",
+                        breakpoint.number,
+                        breakpoint.kind.to_string(),
+                        if breakpoint.enabled { "y" } else { "n" },
+                        breakpoint.hit_count,
+                        breakpoint.location.to_string(),
+                        addresses.join(", "),
+                        condition
+                    );
+                }
+            }
+            Some("debug-registers") => {
+                println!(
+                    "{} of {} hardware debug registers free",
+                    target.free_debug_registers(),
+                    debugreg::NUM_SLOTS
+                );
+            }
+            Some("signals" | "handle") => {
+                print_signal_policy_header();
+                match inp.next() {
+                    Some(signal) => {
+                        let signo = signal::parse_signal(signal)?;
+                        print_signal_policy(signo, target.signals.get(signo));
+                    }
+                    None => {
+                        for (signo, policy) in target.signals.iter() {
+                            print_signal_policy(signo, policy);
+                        }
+                    }
+                }
+            }
+            Some("threads") => {
+                target.ensure_running()?;
+                let base = target.get_base_address()?;
+                println!("  {:<4} {:<16} Location", "Id", "Thread");
+                for thread in target.threads.values() {
+                    let pc = ptrace::get_reg(thread.tid, registers::Register::pc())?;
+                    let location = match dwarf::find_function_at_pc(&target.dwinfo.dwarf, pc, base)? {
+                        None => "??".to_string(),
+                        Some(cp) => cp.summary(),
+                    };
+                    let marker = if thread.number == target.current_thread { "*" } else { " " };
+                    let name = format!("Thread {}", thread.tid.0);
+                    println!("{marker} {:<4} {name:<16} 0x{pc:x} in {location}", thread.number);
+                }
+            }
+            Some(which @ ("locals" | "args")) => {
+                let arguments = which == "args";
+                let variables: Vec<_> = target
+                    .frame_variables()?
+                    .into_iter()
+                    .filter(|(variable, _)| variable.is_argument == arguments)
+                    .collect();
+                if variables.is_empty() {
+                    println!("{}", if arguments { "No arguments." } else { "No locals." });
+                }
+                for (variable, value) in variables {
+                    match value {
+                        Ok(value) => println!("{} = ({}) {value}", variable.name, variable.ty.name),
+                        Err(e) => println!("{} = ({}) <{e}>", variable.name, variable.ty.name),
+                    }
+                }
+            }
+            _ => {
+                return Err("info <args|breakpoints|debug-registers|locals|signals|threads>".into())
+            }
+        },
+        "thread" => match inp.next() {
+            Some(number) => {
+                target.ensure_running()?;
+                target.select_thread(number.parse()?)?;
+                let thread = &target.threads[&target.current_thread];
+                println!("[Switching to thread {} ({})]", thread.number, thread.tid.0);
+                println!("{}", describe_pc(target)?);
+            }
+            None => {
+                let thread = &target.threads[&target.current_thread];
+                println!("Current thread is {} ({})", thread.number, thread.tid.0);
+            }
+        },
+        "catch" => {
+            let event = match inp.next() {
+                Some("exec") => CatchEvent::Exec,
+                Some("syscall") => {
+                    CatchEvent::Syscall(inp.map(syscall::parse_syscall).collect::<Result<_, _>>()?)
+                }
+                _ => return Err("catch <exec|syscall [name|number]...>".into()),
+            };
+            let number = target.add_breakpoint(Location::Event(event), BreakpointKind::Catchpoint)?;
+            println!("Catchpoint {number} ({})", target.user_breakpoints[&number].location);
+        }
+        "condition" => {
+            // condition <number> <expression>?
+            let number = inp.next().ok_or("Give a breakpoint number")?.parse()?;
+            let condition = inp.collect::<Vec<_>>().join(" ");
+            let condition = match condition.is_empty() {
+                true => None,
+                false => Some(condition.parse::<Expr>()?),
+            };
+            target.set_breakpoint_condition(number, condition)?;
+        }
+        "delete" | "del" => {
+            // delete <number>...
+            for number in inp {
+                target.delete_breakpoint(number.parse()?)?;
+            }
+        }
+        "enable" | "disable" => {
+            // enable|disable <number>...
+            for number in inp {
+                target.set_breakpoint_enabled(number.parse()?, command == "enable")?;
+            }
+        }
+        "handle" => {
+            // handle <signal> (stop|nostop|print|noprint|pass|nopass)*
+            let signo = signal::parse_signal(inp.next().ok_or("Give a signal to handle")?)?;
+            let policy = target.signals.get_mut(signo);
+            for keyword in inp {
+                policy.apply(keyword)?;
+            }
+            print_signal_policy_header();
+            print_signal_policy(signo, target.signals.get(signo));
+        }
+        "signal" => {
+            // signal <signal|0>
+            target.ensure_running()?;
+            target.current_thread_mut().pending_signal = match inp.next().ok_or("Give a signal to continue with")? {
+                "0" => None,
+                signal => Some(signal::parse_signal(signal)?),
+            };
+            let reason = target.continue_process()?;
+            print_stop(target, &reason)?;
+        }
+        "queue-signal" => {
+            // queue-signal <signal>
+            target.ensure_running()?;
+            let signo = signal::parse_signal(inp.next().ok_or("Give a signal to queue")?)?;
+            if !target.signals.get(signo).pass {
+                return Err(format!("{} is set to not be passed to the program", signal::signal_name(signo)).into());
+            }
+            target.current_thread_mut().pending_signal = Some(signo);
+        }
+        "run" | "r" | "start" | "starti" => {
+            // run|start|starti <args>*
+            let args: Vec<String> = inp.map(str::to_string).collect();
+            if !args.is_empty() {
+                target.launch.args = args;
+            }
+            target.restart()?;
+            println!("Starting program: {}", target.launch);
+
+            let reason = match command {
+                "start" => target.run_to_main()?,
+                "starti" => {
+                    println!("Program entry point is 0x{:x}", target.entry_point()?);
+                    StopReason::SingleStep
+                }
+                _ => target.continue_process()?,
+            };
+            print_stop(target, &reason)?;
+        }
+        "kill" | "k" => {
             target.kill()?;
+            println!("Killed process {}", child_pid.0);
+        }
+        "set" => match inp.next() {
+            Some("args") => target.launch.args = inp.map(str::to_string).collect(),
+            Some("env" | "environment") => {
+                target.launch.set_env(&inp.collect::<Vec<_>>().join(" "))?;
+            }
+            Some("disable-randomization") => {
+                target.launch.disable_randomization = parse_on_off(inp.next())?;
+            }
+            Some("follow-fork-mode") => {
+                target.follow_fork_mode = inp.next().ok_or("Give parent or child")?.parse()?;
+            }
+            _ => return Err("set <args|env|disable-randomization|follow-fork-mode>".into()),
+        },
+        "unset" => match inp.next() {
+            Some("env" | "environment") => {
+                let name = inp.next().ok_or("Give an environment variable to unset")?;
+                target.launch.unset_env(name);
+            }
+            _ => return Err("unset env <name>".into()),
+        },
+        "tty" => {
+            // tty <path>?
+            target.launch.tty = inp.next().map(str::to_string);
+            if let Some(tty) = &target.launch.tty {
+                std::fs::metadata(tty).map_err(|e| format!("{tty}: {e}"))?;
+            }
+        }
+        "show" => match inp.next() {
+            Some("args") => println!("{}", target.launch.args.join(" ")),
+            Some("disable-randomization") => {
+                let state = if target.launch.disable_randomization { "on" } else { "off" };
+                println!("Disabling randomization of the address space is {state}");
+            }
+            Some("tty") => match &target.launch.tty {
+                Some(tty) => println!("Terminal for future runs: {tty}"),
+                None => println!("Terminal for future runs: the debugger's own"),
+            },
+            Some("env" | "environment") => {
+                for (name, value) in &target.launch.env {
+                    match value {
+                        Some(value) => println!("{name}={value}"),
+                        None => println!("unset {name}"),
+                    }
+                }
+            }
+            Some("follow-fork-mode") => {
+                println!("Debugger response to a program call of fork or vfork is \"{}\"", target.follow_fork_mode);
+            }
+            _ => return Err("show <args|env|tty|disable-randomization|follow-fork-mode>".into()),
+        },
+        "detach" => {
+            target.detach()?;
+            println!("Detached from process {}", child_pid.0);
+            std::process::exit(0);
+        }
+        "exit" => {
+            // Processes we attached to were running before us, so leave them running
+            match (target.exited, target.attached) {
+                (true, _) => {}
+                (false, true) => target.detach()?,
+                (false, false) => target.kill()?,
+            }
             std::process::exit(0);
         }
         "register" | "reg" => match inp.next() {
@@ -90,7 +473,7 @@ fn run_command(target: &mut target::Target, line: &str) -> Result<(), Box<dyn Er
                 let reg =
                     registers::Register::from_str(register_name.to_uppercase().as_str())?;
 
-                let value = ptrace::get_reg(child_pid, reg)?;
+                let value = target.frame_register(reg)?;
                 println!("Register has value: {value:x} = {value}");
             }
             Some("set" | "write" | "w") => {
@@ -99,12 +482,24 @@ fn run_command(target: &mut target::Target, line: &str) -> Result<(), Box<dyn Er
 
                 let value_str = inp.next().ok_or("Expect value to set register to")?;
                 let value: u64 = value_str.parse()?;
-                ptrace::set_reg(child_pid, reg, value)?;
+                if target.selected_frame() != 0 {
+                    return Err("Registers can only be written in the innermost frame".into());
+                }
+                ptrace::set_reg(target.tid(), reg, value)?;
             }
-            None => {
-                let regs = ptrace::get_regs(child_pid)?;
+            None if target.selected_frame() == 0 => {
+                let regs = ptrace::get_regs(target.tid())?;
                 registers::dump_user_regs(&regs);
             }
+            None => {
+                for number in 0..registers::Register::NUM_VARIANTS {
+                    let reg = registers::Register::try_from(number as u8)?;
+                    match target.frame_register(reg) {
+                        Ok(value) => println!("{reg:?}: {value} = 0x{value:x}"),
+                        Err(_) => println!("{reg:?}: <not saved>"),
+                    }
+                }
+            }
             _ => return Err("invalid input".into()),
         },
         "read" => {
@@ -115,7 +510,11 @@ fn run_command(target: &mut target::Target, line: &str) -> Result<(), Box<dyn Er
                 None => (addr_and_type, "i64"),
             };
             dbg!(addr_str, typename);
-            let addr: u64 = parse_address(addr_str)?;
+            // Anything but a plain address is an expression, e.g. `rbp-8` in the selected frame
+            let addr: u64 = match parse_address(addr_str) {
+                Ok(addr) => addr,
+                Err(_) => addr_str.parse::<Expr>()?.evaluate(target)? as u64,
+            };
             dbg!(addr);
 
             // TODO: add char, bool back
@@ -163,3 +562,100 @@ fn run_command(target: &mut target::Target, line: &str) -> Result<(), Box<dyn Er
     };
     Ok(())
 }
+
+/// Reports why the process stopped, along with where it is now.
+fn print_stop(target: &mut target::Target, reason: &StopReason) -> Result<(), Box<dyn Error>> {
+    if let Some(thread) = target.take_thread_switch().filter(|_| !reason.is_exit()) {
+        println!("[Switching to thread {} ({})]", thread.number, thread.tid.0);
+    }
+    match reason {
+        StopReason::Exited(code) => println!("Program exited with code {code}"),
+        StopReason::Killed(signo) => {
+            println!("Program terminated with signal {}", signal::signal_name(*signo))
+        }
+        StopReason::Signal(signo, info) => {
+            println!("Program received {} at {}", signal::signal_name(*signo), describe_pc(target)?);
+            if signal::is_fault(*signo) {
+                println!("Fault address: 0x{:x}", info.addr);
+            }
+        }
+        StopReason::Breakpoint(numbers) if !numbers.is_empty() => {
+            let numbers: Vec<String> = numbers.iter().map(usize::to_string).collect();
+            println!("Breakpoint {}, {}", numbers.join(", "), describe_pc(target)?);
+        }
+        StopReason::Watchpoint { number, old, new } => {
+            let breakpoint = &target.user_breakpoints[number];
+            println!("\n{} {number}: {}", breakpoint.kind, breakpoint.location);
+            match breakpoint.kind {
+                BreakpointKind::Watch { access: WatchAccess::Read, .. } => println!("Value = {new}"),
+                _ => println!("Old value = {old}\nNew value = {new}"),
+            }
+
+            // The trap happens after the accessing instruction, which ends right before the PC
+            let pc = ptrace::get_reg(target.tid(), registers::Register::pc())?;
+            let base = target.get_base_address()?;
+            if let Some(cp) = dwarf::find_function_at_pc(&target.dwinfo.dwarf, pc - 1, base)? {
+                println!("Accessed by {}", cp.summary());
+            }
+        }
+        StopReason::Catchpoint { number, event } => {
+            println!("Catchpoint {number} ({event}), {}", describe_pc(target)?)
+        }
+        StopReason::Exec(program) => {
+            println!("process {} is executing new program: {program}", target.pid().0);
+            println!("{}", describe_pc(target)?);
+        }
+        StopReason::Syscall => println!("Stopped at a system call, {}", describe_pc(target)?),
+        StopReason::PtraceEvent(event) => {
+            println!("Stopped by ptrace event {event}, {}", describe_pc(target)?)
+        }
+        StopReason::Breakpoint(_) | StopReason::SingleStep => println!("{}", describe_pc(target)?),
+    }
+    Ok(())
+}
+
+/// Parses the value of an on/off setting, where nothing means `on`.
+fn parse_on_off(value: Option<&str>) -> Result<bool, String> {
+    match value {
+        None | Some("on") => Ok(true),
+        Some("off") => Ok(false),
+        Some(other) => Err(format!("Expected on or off, got {other}")),
+    }
+}
+
+fn print_signal_policy_header() {
+    println!("{:<10} {:<5} {:<5} Pass", "Signal", "Stop", "Print");
+}
+
+fn print_signal_policy(signo: i32, policy: signal::SignalPolicy) {
+    let yes_no = |flag: bool| if flag { "Yes" } else { "No" };
+    println!(
+        "{:<10} {:<5} {:<5} {}",
+        signal::signal_name(signo),
+        yes_no(policy.stop),
+        yes_no(policy.print),
+        yes_no(policy.pass)
+    );
+}
+
+/// The current PC and its source location, e.g. `0x5555555551a9 in foo (file.c:12)`.
+fn describe_pc(target: &mut target::Target) -> Result<String, Box<dyn Error>> {
+    let pc = ptrace::get_reg(target.tid(), registers::Register::pc())?;
+    let location = match target.current_code_point()? {
+        None => "??".to_string(),
+        Some(cp) => cp.summary(),
+    };
+    Ok(format!("0x{pc:x} in {location}"))
+}
+
+/// A frame as a backtrace shows it, e.g. `0x00005555555551a9 in foo (file.c:12)`.
+fn describe_frame(frame: &target::StackFrame) -> String {
+    match &frame.function {
+        None => format!("0x{:016x} in ??", frame.frame.pc),
+        // Inlined functions have no frame of their own, so no PC either
+        Some(function) if function.inlined => {
+            format!("{:<18} in {}", "[inlined]", function.summary())
+        }
+        Some(function) => format!("0x{:016x} in {}", frame.frame.pc, function.summary()),
+    }
+}
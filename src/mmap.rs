@@ -7,6 +7,8 @@ pub struct Mmap {
 }
 
 impl Mmap {
+    /// # Safety
+    /// The file must not be modified while it is mapped.
     pub unsafe fn map(file: &mut std::fs::File) -> Self {
         let filelen = file.metadata().expect("Failed to get file length").len();
         let res = libc::mmap(
@@ -39,6 +41,8 @@ impl Mmap {
         }
     }
 
+    /// # Safety
+    /// `data` must have come from [`Mmap::leak`].
     pub unsafe fn from_leaked_slice(data: &'static [u8]) -> Self {
         let len = data.len();
         let data = data.as_ptr() as *mut u8;
@@ -46,6 +50,8 @@ impl Mmap {
         Self::from_raw_parts(data, len)
     }
 
+    /// # Safety
+    /// `data` and `len` must describe a live mapping created by `mmap`.
     pub unsafe fn from_raw_parts(data: *mut u8, len: usize) -> Self {
         Self { data, len }
     }
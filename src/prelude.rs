@@ -1,5 +1,7 @@
 use crate::{ptrace, target::Target};
 use crate::dwarf;
+use crate::launch::LaunchSettings;
+use crate::thread;
 
 use std::{ffi, os::unix::process::CommandExt, process};
 
@@ -8,29 +10,86 @@ pub struct Pid(pub i32);
 
 pub const NULLVOID: *const ffi::c_void = std::ptr::null::<ffi::c_void>();
 
-pub fn launch_traceable(mut command: process::Command) -> Result<Target, i32> {
-    match fork::fork()? {
+/// The ptrace options every tracee gets: new threads and processes are traced
+/// automatically, `exec` is reported and system call stops are told apart from
+/// other traps.
+const TRACE_OPTIONS: i32 = libc::PTRACE_O_TRACESYSGOOD
+    | libc::PTRACE_O_TRACECLONE
+    | libc::PTRACE_O_TRACEFORK
+    | libc::PTRACE_O_TRACEVFORK
+    | libc::PTRACE_O_TRACEVFORKDONE
+    | libc::PTRACE_O_TRACEEXEC;
+
+/// Starts the program described by `settings` under ptrace, stopped right after
+/// `exec`, and reads its debug info.
+pub fn launch_traceable(settings: LaunchSettings) -> Result<Target, Box<dyn std::error::Error>> {
+    let pid = spawn_traced(settings.command()?)?;
+    let dwinfo = dwarf::DwarfInfo::load(&settings.program)?;
+    Ok(Target::new(pid, dwinfo, settings))
+}
+
+/// Forks and executes `command` in a traced child, then waits for the `SIGTRAP` the
+/// child stops with once `exec` has loaded the program.
+pub fn spawn_traced(mut command: process::Command) -> Result<Pid, Box<dyn std::error::Error>> {
+    match fork::fork().map_err(|_| std::io::Error::last_os_error())? {
         fork::Fork::Parent(child_pid) => {
-            let program = command.get_program();
-            let dwarf = dwarf::read_dwarf(program.to_str().unwrap()).unwrap();
-            let dwinfo = dwarf::DwarfInfo::new(dwarf);
-            Ok(Target::new(Pid(child_pid), dwinfo))
-        },
-    fork::Fork::Child => {
-            ptrace::trace_me();
+            let pid = Pid(child_pid);
+            let status = wait_for_signal(pid);
+            if !libc::WIFSTOPPED(status) {
+                return Err(format!("Could not start {:?}", command.get_program()).into());
+            }
+            ptrace::set_options(pid, TRACE_OPTIONS)?;
+            Ok(pid)
+        }
+        fork::Fork::Child => {
+            if let Err(err) = ptrace::trace_me() {
+                panic!("Could not trace the child: {err}");
+            }
             // execute the other program (inplace)
-            command.exec();
-            panic!("Bro how did u fail to execute");
+            let err = command.exec();
+            panic!("Bro how did u fail to execute: {err}");
         }
     }
 }
 
-pub fn wait_for_signal(pid: Pid) {
+/// Attaches to every thread of an already running process and stops them. The debug
+/// info is read from the process's executable.
+pub fn attach_traceable(pid: Pid) -> Result<Target, Box<dyn std::error::Error>> {
+    let tids = thread::list_threads(pid)?;
+    for &tid in &tids {
+        ptrace::seize(tid)?;
+        ptrace::interrupt(tid)?;
+        wait_for_signal(tid);
+        ptrace::set_options(tid, TRACE_OPTIONS)?;
+    }
+
+    let dwinfo = dwarf::DwarfInfo::load(&format!("/proc/{}/exe", pid.0))?;
+    let program = std::fs::read_link(format!("/proc/{}/exe", pid.0))?;
+    let settings = LaunchSettings::new(program.to_string_lossy().into_owned(), Vec::new());
+    let mut target = Target::new(pid, dwinfo, settings);
+    target.attached = true;
+    for tid in tids.into_iter().filter(|&tid| tid != pid) {
+        target.add_thread(tid)?;
+    }
+    Ok(target)
+}
+
+/// Waits for `pid` to change state and returns the raw `waitpid` status. `pid` may
+/// be any thread of the process.
+pub fn wait_for_signal(pid: Pid) -> i32 {
     let mut status: i32 = 0;
-    let options: i32 = 0;
     unsafe {
-        libc::waitpid(pid.0, &mut status as *mut i32, options);
+        libc::waitpid(pid.0, &mut status as *mut i32, libc::__WALL);
     }
+    status
+}
+
+/// Waits for any traced thread to change state. Returns which one along with the
+/// raw `waitpid` status, or `None` if there is nothing left to wait for.
+pub fn wait_for_any_signal() -> Option<(Pid, i32)> {
+    let mut status: i32 = 0;
+    let pid = unsafe { libc::waitpid(-1, &mut status as *mut i32, libc::__WALL) };
+    (pid > 0).then_some((Pid(pid), status))
 }
 
 #[macro_export]
@@ -43,7 +102,7 @@ macro_rules! peektype_and_print {
             match $typename {
                 $(
                     stringify!($ty) => {
-                        let val: $ty = ptrace::peekdata_as(pid, addr).unwrap();
+                        let val: $ty = ptrace::peekdata_as(pid, addr)?;
                         println!(
                             "{}, {:02x?}",
                             val,
@@ -91,7 +150,7 @@ pub fn parse_address(s: &str) -> Result<u64, std::num::ParseIntError> {
         Ok(u64::from_str_radix(s, 16)?)
     } else {
         println!("b10 s: {}", s);
-        Ok(u64::from_str_radix(s, 10)?)
+        Ok(s.parse()?)
     }
 }
 
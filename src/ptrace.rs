@@ -1,4 +1,3 @@
-use core::panic;
 use std::fmt::Display;
 use std::mem::MaybeUninit;
 
@@ -8,38 +7,118 @@ use crate::registers::Register;
 use libc;
 // The things in this module should check `errno`
 
-#[derive(Copy, Clone, Debug)]
-pub enum Error {
-    NoSuchProcess = libc::ESRCH as isize,
-    EIO = libc::EIO as isize,
+/// The errno values ptrace reports, named after what they mean for a debugger.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// `ESRCH`: the process doesn't exist, isn't traced by us or isn't stopped.
+    NoSuchProcess,
+    /// `EIO`: an invalid request, or reading or writing an invalid address.
+    EIO,
+    /// `EFAULT`: reading or writing an unmapped address.
+    BadAddress,
+    /// `EPERM`: not allowed to trace the process, e.g. because of `ptrace_scope`.
+    PermissionDenied,
+    /// `EBUSY`: a debug register couldn't be set.
+    Busy,
+    /// `EINVAL`: an invalid option or value, e.g. a debug register setting.
+    InvalidArgument,
+    /// Any other errno.
+    Other(i32),
 }
 
-impl std::error::Error for Error {}
-
-impl From<i32> for Error {
-    fn from(value: i32) -> Self {
-        match value {
+impl From<i32> for ErrorKind {
+    fn from(errno: i32) -> Self {
+        match errno {
+            libc::ESRCH => Self::NoSuchProcess,
             libc::EIO => Self::EIO,
-            libc::ESRCH => Self::EIO,
-            e => panic!("Not a handled error code for ptrace: {e}"),
+            libc::EFAULT => Self::BadAddress,
+            libc::EPERM => Self::PermissionDenied,
+            libc::EBUSY => Self::Busy,
+            libc::EINVAL => Self::InvalidArgument,
+            errno => Self::Other(errno),
         }
     }
 }
 
+/// A failed ptrace call: what went wrong, along with the request and, for requests
+/// that access memory or `struct user`, the address it was made with.
+#[derive(Copy, Clone, Debug)]
+pub struct Error {
+    pub kind: ErrorKind,
+    pub errno: i32,
+    pub request: libc::c_uint,
+    pub addr: Option<u64>,
+}
+
+impl Error {
+    pub fn new(errno: i32, request: libc::c_uint, addr: Option<u64>) -> Self {
+        Self {
+            kind: errno.into(),
+            errno,
+            request,
+            addr,
+        }
+    }
+
+    /// The error for `request` on `addr` that just failed, taken from `errno`.
+    fn last(request: libc::c_uint, addr: Option<u64>) -> Self {
+        let errno = unsafe { *libc::__errno_location() };
+        Self::new(errno, request, addr)
+    }
+}
+
+impl std::error::Error for Error {}
+
 impl Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Error::NoSuchProcess => write!(f, "NoSuchProcess"),
-            Error::EIO => write!(f, "EIO"),
+        let reason = match self.kind {
+            ErrorKind::NoSuchProcess => "no such process, or it is not stopped".to_string(),
+            ErrorKind::EIO | ErrorKind::BadAddress => "cannot access memory".to_string(),
+            ErrorKind::PermissionDenied => "operation not permitted".to_string(),
+            ErrorKind::Busy => "debug register is busy".to_string(),
+            ErrorKind::InvalidArgument => "invalid argument".to_string(),
+            ErrorKind::Other(errno) => std::io::Error::from_raw_os_error(errno).to_string(),
+        };
+        write!(f, "{}", request_name(self.request))?;
+        if let Some(addr) = self.addr {
+            write!(f, " at 0x{addr:x}")?;
         }
+        write!(f, " failed: {reason} (errno {})", self.errno)
     }
 }
 
-fn check_errno() -> Option<Error> {
+/// The name of a `PTRACE_*` request, for error messages.
+fn request_name(request: libc::c_uint) -> String {
+    let name = match request {
+        libc::PTRACE_TRACEME => "PTRACE_TRACEME",
+        libc::PTRACE_PEEKDATA => "PTRACE_PEEKDATA",
+        libc::PTRACE_PEEKUSER => "PTRACE_PEEKUSER",
+        libc::PTRACE_POKEDATA => "PTRACE_POKEDATA",
+        libc::PTRACE_POKEUSER => "PTRACE_POKEUSER",
+        libc::PTRACE_CONT => "PTRACE_CONT",
+        libc::PTRACE_SINGLESTEP => "PTRACE_SINGLESTEP",
+        libc::PTRACE_SYSCALL => "PTRACE_SYSCALL",
+        libc::PTRACE_GETREGS => "PTRACE_GETREGS",
+        libc::PTRACE_SETREGS => "PTRACE_SETREGS",
+        libc::PTRACE_GETFPREGS => "PTRACE_GETFPREGS",
+        libc::PTRACE_GETSIGINFO => "PTRACE_GETSIGINFO",
+        libc::PTRACE_DETACH => "PTRACE_DETACH",
+        libc::PTRACE_SEIZE => "PTRACE_SEIZE",
+        libc::PTRACE_INTERRUPT => "PTRACE_INTERRUPT",
+        libc::PTRACE_SETOPTIONS => "PTRACE_SETOPTIONS",
+        libc::PTRACE_GETEVENTMSG => "PTRACE_GETEVENTMSG",
+        request => return format!("ptrace request {request}"),
+    };
+    name.to_string()
+}
+
+/// The error for `request` on `addr` if `errno` is set. Needed for the `PEEK`
+/// requests, where -1 is also a valid result.
+fn check_errno(request: libc::c_uint, addr: Option<u64>) -> Option<Error> {
     let errno: i32 = unsafe { *libc::__errno_location() };
     match errno {
         0 => None,
-        err => Some(err.into()),
+        errno => Some(Error::new(errno, request, addr)),
     }
 }
 
@@ -53,16 +132,68 @@ pub fn has_errno() -> bool {
     unsafe { *libc::__errno_location() == 0 }
 }
 
-pub fn trace_me() {
-    unsafe {
-        libc::ptrace(libc::PTRACE_TRACEME, 0, 0, NULLVOID);
+pub fn trace_me() -> Result<(), Error> {
+    let res = unsafe { libc::ptrace(libc::PTRACE_TRACEME, 0, NULLVOID, NULLVOID) };
+    match res {
+        -1 => Err(Error::last(libc::PTRACE_TRACEME, None)),
+        _ => Ok(()),
+    }
+}
+
+/// Attaches to a running process without stopping it.
+pub fn seize(pid: Pid) -> Result<(), Error> {
+    let res = unsafe { libc::ptrace(libc::PTRACE_SEIZE, pid.0, NULLVOID, NULLVOID) };
+    match res {
+        -1 => Err(Error::last(libc::PTRACE_SEIZE, None)),
+        _ => Ok(()),
+    }
+}
+
+/// Stops a process attached with [`seize`].
+pub fn interrupt(pid: Pid) -> Result<(), Error> {
+    let res = unsafe { libc::ptrace(libc::PTRACE_INTERRUPT, pid.0, NULLVOID, NULLVOID) };
+    match res {
+        -1 => Err(Error::last(libc::PTRACE_INTERRUPT, None)),
+        _ => Ok(()),
+    }
+}
+
+/// Sets the `PTRACE_O_*` options of a stopped tracee.
+pub fn set_options(pid: Pid, options: i32) -> Result<(), Error> {
+    let res = unsafe { libc::ptrace(libc::PTRACE_SETOPTIONS, pid.0, NULLVOID, options as usize) };
+    match res {
+        -1 => Err(Error::last(libc::PTRACE_SETOPTIONS, None)),
+        _ => Ok(()),
+    }
+}
+
+/// The message of the current `PTRACE_EVENT_*` stop, e.g. the new thread's id for
+/// `PTRACE_EVENT_CLONE`.
+pub fn get_event_message(pid: Pid) -> Result<u64, Error> {
+    let mut message: libc::c_ulong = 0;
+    let res = unsafe {
+        libc::ptrace(libc::PTRACE_GETEVENTMSG, pid.0, NULLVOID, &mut message as *mut libc::c_ulong)
     };
+    match res {
+        -1 => Err(Error::last(libc::PTRACE_GETEVENTMSG, None)),
+        _ => Ok(message),
+    }
+}
+
+/// Stops tracing a process and lets it run freely, delivering `signal` to it unless
+/// it is 0.
+pub fn detach(pid: Pid, signal: i32) -> Result<(), Error> {
+    let res = unsafe { libc::ptrace(libc::PTRACE_DETACH, pid.0, NULLVOID, signal as usize) };
+    match res {
+        -1 => Err(Error::last(libc::PTRACE_DETACH, None)),
+        _ => Ok(()),
+    }
 }
 
 pub fn peekdata(pid: Pid, addr: u64) -> Result<i64, Error> {
     clear_errno();
     let data = unsafe { libc::ptrace(libc::PTRACE_PEEKDATA, pid.0, addr, NULLVOID) };
-    match check_errno() {
+    match check_errno(libc::PTRACE_PEEKDATA, Some(addr)) {
         None => Ok(data),
         Some(err) => Err(err),
     }
@@ -110,9 +241,9 @@ pub fn peekdata_as<T: Sized>(pid: Pid, addr: u64) -> Result<T, Error> {
 
 pub fn pokedata(pid: Pid, addr: u64, data: i64) -> Result<(), Error> {
     clear_errno();
-    let res = unsafe { libc::ptrace(libc::PTRACE_POKEDATA, pid, addr, data) };
+    let res = unsafe { libc::ptrace(libc::PTRACE_POKEDATA, pid.0, addr, data) };
     match res {
-        -1 => Err(check_errno().unwrap()),
+        -1 => Err(Error::last(libc::PTRACE_POKEDATA, Some(addr))),
         _ => Ok(()),
     }
 }
@@ -125,7 +256,8 @@ pub fn pokedata_slice(pid: Pid, mut addr: u64, data: &[u8]) -> Result<(), Error>
         let bytes_to_copy = remaining.len().min(8);
 
         if bytes_to_copy == 8 {
-            pokedata(pid, addr, remaining.as_ptr() as i64)?;
+            let word = remaining[..8].try_into().expect("Checked there are 8 bytes");
+            pokedata(pid, addr, i64::from_ne_bytes(word))?;
         }
         else {
             let word_at_addr: i64 = peekdata(pid, addr)?;
@@ -160,11 +292,43 @@ pub fn pokedata_as<T: Sized>(pid: Pid, addr: u64, data: &T) -> Result<(), Error>
 }
 
 
-pub fn cont(pid: Pid) -> Result<(), Error> {
-    let res = unsafe { libc::ptrace(libc::PTRACE_CONT, pid.0, NULLVOID, NULLVOID) };
+/// Resumes the process, delivering `signal` to it unless it is 0.
+pub fn cont(pid: Pid, signal: i32) -> Result<(), Error> {
+    let res = unsafe { libc::ptrace(libc::PTRACE_CONT, pid.0, NULLVOID, signal as usize) };
 
     match res {
-        -1 => Err(check_errno().unwrap()),
+        -1 => Err(Error::last(libc::PTRACE_CONT, None)),
+        _ => Ok(()),
+    }
+}
+
+/// Resumes the process until it enters or leaves a system call, delivering `signal`
+/// to it unless it is 0.
+pub fn syscall(pid: Pid, signal: i32) -> Result<(), Error> {
+    let res = unsafe { libc::ptrace(libc::PTRACE_SYSCALL, pid.0, NULLVOID, signal as usize) };
+
+    match res {
+        -1 => Err(Error::last(libc::PTRACE_SYSCALL, None)),
+        _ => Ok(()),
+    }
+}
+
+/// Reads a word at `offset` into the tracee's `struct user`.
+pub fn peekuser(pid: Pid, offset: usize) -> Result<u64, Error> {
+    clear_errno();
+    let data = unsafe { libc::ptrace(libc::PTRACE_PEEKUSER, pid.0, offset, NULLVOID) };
+    match check_errno(libc::PTRACE_PEEKUSER, Some(offset as u64)) {
+        None => Ok(data as u64),
+        Some(err) => Err(err),
+    }
+}
+
+/// Writes a word at `offset` into the tracee's `struct user`.
+pub fn pokeuser(pid: Pid, offset: usize, data: u64) -> Result<(), Error> {
+    clear_errno();
+    let res = unsafe { libc::ptrace(libc::PTRACE_POKEUSER, pid.0, offset, data) };
+    match res {
+        -1 => Err(Error::last(libc::PTRACE_POKEUSER, Some(offset as u64))),
         _ => Ok(()),
     }
 }
@@ -174,18 +338,41 @@ pub fn get_regs(pid: Pid) -> Result<libc::user_regs_struct, Error> {
         let mut regs = MaybeUninit::<libc::user_regs_struct>::uninit();
         let res = libc::ptrace(libc::PTRACE_GETREGS, pid.0, NULLVOID, regs.as_mut_ptr());
         match res {
-            -1 => Err(check_errno().unwrap()),
+            -1 => Err(Error::last(libc::PTRACE_GETREGS, None)),
+            _ => Ok(regs.assume_init()),
+        }
+    }
+}
+
+pub fn get_fpregs(pid: Pid) -> Result<libc::user_fpregs_struct, Error> {
+    unsafe {
+        let mut regs = MaybeUninit::<libc::user_fpregs_struct>::uninit();
+        let res = libc::ptrace(libc::PTRACE_GETFPREGS, pid.0, NULLVOID, regs.as_mut_ptr());
+        match res {
+            -1 => Err(Error::last(libc::PTRACE_GETFPREGS, None)),
             _ => Ok(regs.assume_init()),
         }
     }
 }
 
+/// Reads the `siginfo_t` of the signal that caused the current stop.
+pub fn get_siginfo(pid: Pid) -> Result<libc::siginfo_t, Error> {
+    unsafe {
+        let mut info = MaybeUninit::<libc::siginfo_t>::uninit();
+        let res = libc::ptrace(libc::PTRACE_GETSIGINFO, pid.0, NULLVOID, info.as_mut_ptr());
+        match res {
+            -1 => Err(Error::last(libc::PTRACE_GETSIGINFO, None)),
+            _ => Ok(info.assume_init()),
+        }
+    }
+}
+
 pub fn set_regs(pid: Pid, regs: &libc::user_regs_struct) -> Result<(), Error> {
     unsafe {
         let r = regs as *const _;
         let res = libc::ptrace(libc::PTRACE_SETREGS, pid.0, NULLVOID, r);
         match res {
-            -1 => Err(check_errno().unwrap()),
+            -1 => Err(Error::last(libc::PTRACE_SETREGS, None)),
             _ => Ok(()),
         }
     }
@@ -204,11 +391,12 @@ pub fn get_reg(pid: Pid, reg: Register) -> Result<u64, Error> {
     Ok(*reg.extract_from_reg_struct(&regs))
 }
 
-pub fn single_step(pid: Pid) -> Result<(), Error> {
+/// Executes one instruction, delivering `signal` first unless it is 0.
+pub fn single_step(pid: Pid, signal: i32) -> Result<(), Error> {
     unsafe {
-        let res = libc::ptrace(libc::PTRACE_SINGLESTEP, pid.0, NULLVOID, NULLVOID);
+        let res = libc::ptrace(libc::PTRACE_SINGLESTEP, pid.0, NULLVOID, signal as usize);
         match res {
-            -1 => Err(check_errno().unwrap()),
+            -1 => Err(Error::last(libc::PTRACE_SINGLESTEP, None)),
             _ => Ok(()),
         }
     }
@@ -43,7 +43,9 @@ impl Register {
         Self::RIP
     }
 
-    fn dwarf(&self) -> i32 {
+    /// The DWARF register number, with the return address column standing in for
+    /// RIP. -1 for registers DWARF doesn't number.
+    pub fn dwarf(&self) -> i32 {
         match self {
             Self::R15 => 15,
             Self::R14 => 14,
@@ -61,7 +63,7 @@ impl Register {
             Self::RSI => 4,
             Self::RDI => 5,
             Self::ORIGRAX => -1,
-            Self::RIP => -1,
+            Self::RIP => 16,
             Self::CS => 51,
             Self::RFLAGS => 49,
             Self::RSP => 7,
@@ -75,7 +77,7 @@ impl Register {
         }
     }
 
-    fn from_dwarf(dwarf: i32) -> Option<Self> {
+    pub fn from_dwarf(dwarf: i32) -> Option<Self> {
         let reg = match dwarf {
             15 => Self::R15,
             14 => Self::R14,
@@ -92,6 +94,7 @@ impl Register {
             1 => Self::RDX,
             4 => Self::RSI,
             5 => Self::RDI,
+            16 => Self::RIP,
             51 => Self::CS,
             49 => Self::RFLAGS,
             7 => Self::RSP,
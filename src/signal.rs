@@ -0,0 +1,129 @@
+/// Standard Linux x86_64 signal names, indexed by signal number.
+const SIGNAL_NAMES: [&str; 32] = [
+    "0", "SIGHUP", "SIGINT", "SIGQUIT", "SIGILL", "SIGTRAP", "SIGABRT", "SIGBUS", "SIGFPE",
+    "SIGKILL", "SIGUSR1", "SIGSEGV", "SIGUSR2", "SIGPIPE", "SIGALRM", "SIGTERM", "SIGSTKFLT",
+    "SIGCHLD", "SIGCONT", "SIGSTOP", "SIGTSTP", "SIGTTIN", "SIGTTOU", "SIGURG", "SIGXCPU",
+    "SIGXFSZ", "SIGVTALRM", "SIGPROF", "SIGWINCH", "SIGIO", "SIGPWR", "SIGSYS",
+];
+
+/// The largest valid signal number, including realtime signals.
+pub fn max_signal() -> i32 {
+    libc::SIGRTMAX()
+}
+
+/// The name of signal `signo`, e.g. `SIGSEGV` or `SIG34` for realtime signals.
+pub fn signal_name(signo: i32) -> String {
+    match SIGNAL_NAMES.get(signo as usize) {
+        Some(name) if signo > 0 => name.to_string(),
+        _ => format!("SIG{signo}"),
+    }
+}
+
+/// Parses a signal given as a name with or without the `SIG` prefix (`SIGINT`,
+/// `int`), as `SIG34`, or as a plain number.
+pub fn parse_signal(s: &str) -> Result<i32, String> {
+    let upper = s.to_uppercase();
+    let name = upper.strip_prefix("SIG").unwrap_or(&upper);
+
+    let signo = match name.parse::<i32>() {
+        Ok(signo) => signo,
+        Err(_) => SIGNAL_NAMES
+            .iter()
+            .position(|known| known.strip_prefix("SIG") == Some(name))
+            .map(|signo| signo as i32)
+            .ok_or_else(|| format!("Unknown signal {s}"))?,
+    };
+
+    if !(1..=max_signal()).contains(&signo) {
+        return Err(format!("Signal {signo} is out of range"));
+    }
+    Ok(signo)
+}
+
+/// Whether `signo` is a hardware fault, where the siginfo carries the faulting address.
+pub fn is_fault(signo: i32) -> bool {
+    matches!(signo, libc::SIGSEGV | libc::SIGBUS | libc::SIGILL | libc::SIGFPE)
+}
+
+/// What to do when the process receives a signal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SignalPolicy {
+    /// Return control to the user.
+    pub stop: bool,
+    /// Report that the signal was received.
+    pub print: bool,
+    /// Deliver the signal to the program when it is resumed.
+    pub pass: bool,
+}
+
+impl SignalPolicy {
+    /// Applies a `handle` keyword. Stopping implies printing, so `stop` turns on
+    /// `print` and `noprint` turns off `stop`.
+    pub fn apply(&mut self, keyword: &str) -> Result<(), String> {
+        match keyword {
+            "stop" => (self.stop, self.print) = (true, true),
+            "nostop" => self.stop = false,
+            "print" => self.print = true,
+            "noprint" => (self.stop, self.print) = (false, false),
+            "pass" | "noignore" => self.pass = true,
+            "nopass" | "ignore" => self.pass = false,
+            _ => return Err(format!("Unknown handle keyword {keyword}")),
+        }
+        Ok(())
+    }
+}
+
+/// The [`SignalPolicy`] of every signal, indexed by signal number.
+#[derive(Debug, Clone)]
+pub struct SignalTable {
+    policies: Vec<SignalPolicy>,
+}
+
+impl Default for SignalTable {
+    /// Signals programs use for bookkeeping (timers, child status, window size)
+    /// are passed through silently. The ones the debugger itself uses are kept
+    /// from the program, and everything else stops and is passed on.
+    fn default() -> Self {
+        let policies = (0..=max_signal())
+            .map(|signo| match signo {
+                libc::SIGALRM
+                | libc::SIGURG
+                | libc::SIGCHLD
+                | libc::SIGWINCH
+                | libc::SIGIO
+                | libc::SIGVTALRM
+                | libc::SIGPROF => SignalPolicy {
+                    stop: false,
+                    print: false,
+                    pass: true,
+                },
+                libc::SIGINT | libc::SIGTRAP => SignalPolicy {
+                    stop: true,
+                    print: true,
+                    pass: false,
+                },
+                _ => SignalPolicy {
+                    stop: true,
+                    print: true,
+                    pass: true,
+                },
+            })
+            .collect();
+        Self { policies }
+    }
+}
+
+impl SignalTable {
+    pub fn get(&self, signo: i32) -> SignalPolicy {
+        self.policies[signo as usize]
+    }
+
+    pub fn get_mut(&mut self, signo: i32) -> &mut SignalPolicy {
+        &mut self.policies[signo as usize]
+    }
+
+    /// Every valid signal number with its policy.
+    pub fn iter(&self) -> impl Iterator<Item = (i32, SignalPolicy)> + '_ {
+        self.policies.iter().enumerate().skip(1).map(|(signo, policy)| (signo as i32, *policy))
+    }
+}
@@ -0,0 +1,94 @@
+use crate::dwarf::types::TypedValue;
+use crate::prelude::*;
+use crate::ptrace;
+
+/// The parts of a `siginfo_t` worth reporting.
+#[derive(Debug, Clone, Copy)]
+pub struct SignalInfo {
+    pub signo: i32,
+    /// `si_code`, e.g. `SEGV_MAPERR` or `SI_USER`.
+    pub code: i32,
+    /// The faulting address for SIGSEGV, SIGBUS, SIGILL and SIGFPE, otherwise 0.
+    pub addr: u64,
+}
+
+impl SignalInfo {
+    /// Reads the siginfo of the signal `pid` is currently stopped with.
+    pub fn of(pid: Pid) -> Result<Self, ptrace::Error> {
+        let info = ptrace::get_siginfo(pid)?;
+        Ok(Self {
+            signo: info.si_signo,
+            code: info.si_code,
+            addr: unsafe { info.si_addr() } as u64,
+        })
+    }
+}
+
+/// Why the process stopped, or why it is gone.
+#[derive(Debug, Clone)]
+pub enum StopReason {
+    /// A breakpoint trap. Holds the numbers of the user breakpoints that were hit,
+    /// which is empty when only an internal breakpoint was hit.
+    Breakpoint(Vec<usize>),
+    /// The watchpoint `number` triggered. For read watchpoints `old` and `new` are equal.
+    Watchpoint {
+        number: usize,
+        old: TypedValue,
+        new: TypedValue,
+    },
+    /// A single step finished.
+    SingleStep,
+    /// Stopped by a signal that has not been delivered yet.
+    Signal(i32, SignalInfo),
+    Exited(i32),
+    Killed(i32),
+    /// Entered or left a system call (with `PTRACE_O_TRACESYSGOOD`).
+    Syscall,
+    /// The process called `exec` to run the program at this path.
+    Exec(String),
+    /// Catchpoint `number` caught `event`, described for the user.
+    Catchpoint { number: usize, event: String },
+    /// A `PTRACE_EVENT_*` stop.
+    PtraceEvent(i32),
+}
+
+impl StopReason {
+    /// Decodes the `waitpid` status of `pid`. Traps are told apart by the `si_code`
+    /// of their siginfo: `INT3` reports `SI_KERNEL`, debug registers `TRAP_HWBKPT`
+    /// and single steps `TRAP_TRACE`.
+    pub fn from_wait_status(pid: Pid, status: i32) -> Result<Self, ptrace::Error> {
+        if libc::WIFEXITED(status) {
+            return Ok(Self::Exited(libc::WEXITSTATUS(status)));
+        }
+        if libc::WIFSIGNALED(status) {
+            return Ok(Self::Killed(libc::WTERMSIG(status)));
+        }
+        assert!(libc::WIFSTOPPED(status), "waitpid returned a status that is not a stop: {status:x}");
+
+        let signo = libc::WSTOPSIG(status);
+        if signo == libc::SIGTRAP | 0x80 {
+            return Ok(Self::Syscall);
+        }
+        let event = status >> 16;
+        if event != 0 {
+            return Ok(Self::PtraceEvent(event));
+        }
+
+        let info = SignalInfo::of(pid)?;
+        if signo != libc::SIGTRAP {
+            return Ok(Self::Signal(signo, info));
+        }
+        let reason = match info.code {
+            libc::SI_KERNEL | libc::TRAP_BRKPT | libc::TRAP_HWBKPT => Self::Breakpoint(Vec::new()),
+            libc::TRAP_TRACE => Self::SingleStep,
+            // Sent by the program itself, e.g. with `raise(SIGTRAP)`
+            _ => Self::Signal(signo, info),
+        };
+        Ok(reason)
+    }
+
+    /// Whether the process no longer exists.
+    pub fn is_exit(&self) -> bool {
+        matches!(self, Self::Exited(_) | Self::Killed(_))
+    }
+}
@@ -1,96 +1,1703 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt::Debug;
 use std::io::BufRead;
 
-use crate::breakpoint::Breakpoint;
-use crate::dwarf::DwarfInfo;
+use crate::breakpoint::{
+    Breakpoint, BreakpointKind, CatchEvent, Location, UserBreakpoint, WatchAccess,
+};
+use crate::debugreg;
+use crate::dwarf::location::{
+    dwarf_register, evaluate_value, read_location, LocationContext, OPTIMIZED_OUT,
+};
+use crate::dwarf::types::{EightbyteClass, ReturnLocation, TypeInfo, TypedValue};
+use crate::dwarf::unwind::{unwind, CallFrameInfo, Frame, FrameRegisters, Module};
+use crate::dwarf::{
+    find_function_at_pc, find_inline_frames_at_pc, find_subprogram_at_pc, CodePoint, DwarfInfo,
+    InlineFrame, StaticEndianSlice,
+};
+use crate::dwarf::variables::{
+    base_value_type, find_call_site_value, find_variables_at_pc, Variable, VariableLocation,
+};
+use crate::expr::Expr;
+use crate::launch::LaunchSettings;
 use crate::prelude::*;
 use crate::ptrace;
 use crate::registers::Register;
+use crate::signal::{signal_name, SignalTable};
+use crate::stop::{SignalInfo, StopReason};
+use crate::syscall::syscall_name;
+use crate::thread::{tgkill, Thread};
+
+/// The value of a variable, or why it couldn't be read.
+pub type VariableValue = Result<TypedValue, String>;
+
+/// The value a function returned, or why it couldn't be read.
+pub type ReturnedValue = Result<TypedValue, String>;
+
+/// A frame of the call stack as the user sees it.
+#[derive(Debug, Clone)]
+pub struct StackFrame {
+    /// The real frame, shared by every function inlined into it.
+    pub frame: Frame,
+    /// The level of the real frame among the real frames only.
+    pub real_level: usize,
+    /// The function, or `None` without debug info for the PC.
+    pub function: Option<InlineFrame>,
+}
+
+/// Evaluates locations from `unit` in frame `level` of `stack`.
+struct FrameContext<'a> {
+    target: &'a Target,
+    stack: &'a [StackFrame],
+    level: usize,
+    base: u64,
+    unit: gimli::DebugInfoOffset,
+}
+
+impl LocationContext for FrameContext<'_> {
+    fn register(&mut self, register: Register) -> Result<u64, String> {
+        self.target.register_in(&self.stack[self.level], register).map_err(|e| e.to_string())
+    }
+
+    /// XMM registers are all caller-saved, so only the innermost frame has them.
+    fn xmm_register(&mut self, number: usize) -> Result<[u8; 16], String> {
+        if self.stack[self.level].real_level != 0 {
+            return Err(format!("XMM{number} was not saved in this frame"));
+        }
+        let fpregs = ptrace::get_fpregs(self.target.tid()).map_err(|e| e.to_string())?;
+        let mut bytes = [0; 16];
+        for (chunk, word) in bytes.chunks_mut(4).zip(&fpregs.xmm_space[number * 4..]) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        Ok(bytes)
+    }
+
+    fn read_memory(&mut self, address: u64, bytes: &mut [u8]) -> Result<(), String> {
+        ptrace::peekdata_slice(self.target.pid, address, bytes).map_err(|e| e.to_string())
+    }
+
+    fn cfa(&mut self) -> Result<u64, String> {
+        self.stack[self.level].frame.cfa.ok_or_else(|| "the frame's CFA is unknown".to_string())
+    }
+
+    fn relocate(&mut self, address: u64) -> u64 {
+        address + self.base
+    }
+
+    /// Only registers can be recovered: callee-saved ones still hold what the caller
+    /// had, and the compiler may describe what the caller passed in the others.
+    fn entry_value(
+        &mut self,
+        expression: gimli::Expression<StaticEndianSlice>,
+        encoding: gimli::Encoding,
+    ) -> Result<u64, String> {
+        let mut operations = expression.operations(encoding);
+        // Registers holding floats are given as `DW_OP_regval_type`
+        let register = match (operations.next(), operations.next()) {
+            (Ok(Some(gimli::Operation::Register { register })), Ok(None)) => register,
+            (Ok(Some(gimli::Operation::RegisterOffset { register, offset: 0, .. })), Ok(None)) => {
+                register
+            }
+            _ => return Err("unsupported entry value".to_string()),
+        };
+        let real_level = self.stack[self.level].real_level;
+        let caller_level = self.stack.iter().position(|frame| frame.real_level == real_level + 1);
+        let Some(caller_level) = caller_level else {
+            return Err(OPTIMIZED_OUT.to_string());
+        };
+        let mut caller = FrameContext { level: caller_level, ..*self };
+
+        let callee_saved = dwarf_register(register).ok().filter(|reg| {
+            matches!(
+                reg,
+                Register::RBX | Register::RBP | Register::R12 | Register::R13 | Register::R14 | Register::R15
+            )
+        });
+        if let Some(reg) = callee_saved {
+            return caller.register(reg);
+        }
+        let return_address = self.stack[caller_level].frame.pc;
+        let dwarf = &self.target.dwinfo.dwarf;
+        let call_site = find_call_site_value(dwarf, return_address.wrapping_sub(self.base), register)
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| OPTIMIZED_OUT.to_string())?;
+        caller.unit = call_site.unit;
+        evaluate_value(call_site.value, call_site.encoding, call_site.frame_base, &mut caller)
+    }
+
+    fn base_type(&mut self, offset: gimli::UnitOffset) -> Result<gimli::ValueType, String> {
+        base_value_type(&self.target.dwinfo.dwarf, self.unit, offset)
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| "unsupported base type in a location expression".to_string())
+    }
+}
+
+/// Which process to keep debugging when the program forks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FollowForkMode {
+    Parent,
+    Child,
+}
+
+impl std::str::FromStr for FollowForkMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "parent" => Ok(Self::Parent),
+            "child" => Ok(Self::Child),
+            _ => Err(format!("Expected parent or child, got {s}")),
+        }
+    }
+}
+
+impl std::fmt::Display for FollowForkMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Parent => write!(f, "parent"),
+            Self::Child => write!(f, "child"),
+        }
+    }
+}
 
 pub struct Target {
+    /// The process id, which is also the thread id of the main thread.
     pub pid: Pid,
+    /// Every thread of the process, by thread number.
+    pub threads: BTreeMap<usize, Thread>,
+    next_thread_number: usize,
+    /// The thread registers are read from and that stepping applies to.
+    pub current_thread: usize,
+    /// The thread the user last saw, to tell them when a stop switches threads.
+    reported_thread: usize,
+    /// The thread being single-stepped while the others are held stopped, or `None`
+    /// when resuming lets every thread run.
+    stepping_thread: Option<usize>,
+    /// The level of the stack frame registers are read from, as numbered by
+    /// `backtrace`. Every stop selects the innermost frame again.
+    selected_frame: usize,
+    /// Every address currently patched with an `INT3`.
     pub breakpoints: HashMap<u64, Breakpoint>,
+    /// Numbered breakpoints the user asked for, each owning one or more addresses.
+    pub user_breakpoints: BTreeMap<usize, UserBreakpoint>,
+    next_breakpoint_number: usize,
+    /// Internal breakpoints used while stepping, never shown to the user.
+    temporary_breakpoints: HashSet<u64>,
+    /// Which user breakpoint and address, if any, owns each of DR0-DR3.
+    debug_register_slots: [Option<(usize, u64)>; debugreg::NUM_SLOTS],
     pub base_address: Option<u64>,
     pub dwinfo: DwarfInfo,
     pub last_step_was_breakpoint: bool,
+    /// Whether we attached to a process that was already running, rather than
+    /// launching it ourselves.
+    pub attached: bool,
+    /// Whether the process has exited or was killed, after which it can't be resumed.
+    pub exited: bool,
+    /// Whether to stop for, report and pass on each signal.
+    pub signals: SignalTable,
+    /// How to start the program again for `run`.
+    pub launch: LaunchSettings,
+    /// Which process to keep debugging when the program forks.
+    pub follow_fork_mode: FollowForkMode,
+    /// New processes whose first stop arrived before their parent's fork event.
+    stopped_new_processes: HashSet<Pid>,
+    /// Whether `dwinfo` was read from a program the process `exec`ed, rather than
+    /// the one `run` starts.
+    dwinfo_from_exec: bool,
+    /// The call frame information of shared libraries, by path, read the first
+    /// time a backtrace goes through them.
+    library_cfi: HashMap<String, CallFrameInfo>,
 }
 
 impl Target {
-    pub fn new(pid: Pid, dwinfo: DwarfInfo) -> Self {
+    pub fn new(pid: Pid, dwinfo: DwarfInfo, launch: LaunchSettings) -> Self {
         Self {
             pid,
+            threads: BTreeMap::from([(1, Thread::new(1, pid))]),
+            next_thread_number: 2,
+            current_thread: 1,
+            reported_thread: 1,
+            stepping_thread: None,
+            selected_frame: 0,
             breakpoints: HashMap::default(),
+            user_breakpoints: BTreeMap::default(),
+            next_breakpoint_number: 1,
+            temporary_breakpoints: HashSet::default(),
+            debug_register_slots: [None; debugreg::NUM_SLOTS],
             base_address: None,
             dwinfo,
             last_step_was_breakpoint: false,
+            attached: false,
+            exited: false,
+            signals: SignalTable::default(),
+            launch,
+            follow_fork_mode: FollowForkMode::Parent,
+            stopped_new_processes: HashSet::default(),
+            dwinfo_from_exec: false,
+            library_cfi: HashMap::default(),
         }
     }
 
-    pub fn add_breakpoint_at(&mut self, addr: u64) -> Result<(), ptrace::Error> {
-        let breakpoint = self
-            .breakpoints
-            .entry(addr)
-            .or_insert_with(|| Breakpoint::new(self.pid, addr));
+    /// Starts the program again from the beginning, killing the current process if
+    /// there still is one. The new process is stopped right after `exec`, with every
+    /// breakpoint resolved again against its base address.
+    pub fn restart(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        // Open any redirections first, so a typo doesn't cost us the current process
+        let command = self.launch.command()?;
+        if !self.exited {
+            self.kill()?;
+        }
 
-        breakpoint.enable()
+        self.pid = spawn_traced(command)?;
+        self.threads = BTreeMap::from([(1, Thread::new(1, self.pid))]);
+        self.next_thread_number = 2;
+        self.current_thread = 1;
+        self.reported_thread = 1;
+        self.stepping_thread = None;
+        self.selected_frame = 0;
+        self.exited = false;
+        self.attached = false;
+        self.clear_base_address();
+        self.breakpoints.clear();
+        self.temporary_breakpoints.clear();
+        self.debug_register_slots = [None; debugreg::NUM_SLOTS];
+        self.last_step_was_breakpoint = false;
+
+        if self.dwinfo_from_exec {
+            self.dwinfo = DwarfInfo::load(&self.launch.program)?;
+            self.dwinfo_from_exec = false;
+        }
+        self.reresolve_breakpoints();
+        Ok(())
+    }
+
+    /// Resolves every breakpoint again, e.g. against a new base address, reporting
+    /// the ones that no longer resolve.
+    fn reresolve_breakpoints(&mut self) {
+        let numbers: Vec<usize> = self.user_breakpoints.keys().copied().collect();
+        for number in numbers {
+            if let Err(err) = self.reresolve_breakpoint(number) {
+                println!("Could not re-set breakpoint {number}: {err}");
+            }
+        }
     }
 
-    pub fn add_breakpoint_at_function(
+    /// Resolves the location of breakpoint `number` again and puts it into the
+    /// process. A breakpoint that no longer resolves is kept without addresses.
+    fn reresolve_breakpoint(&mut self, number: usize) -> Result<(), Box<dyn std::error::Error>> {
+        let breakpoint = self.user_breakpoints.get_mut(&number).expect("Breakpoint exists");
+        breakpoint.addresses.clear();
+        let location = breakpoint.location.clone();
+        let addresses = self.resolve_location(&location)?;
+
+        let pid = self.pid;
+        let breakpoint = self.user_breakpoints.get_mut(&number).expect("Breakpoint exists");
+        breakpoint.addresses = addresses.clone();
+        if let BreakpointKind::Watch { old_value, .. } = &mut breakpoint.kind {
+            ptrace::peekdata_slice(pid, addresses[0], old_value)?;
+        }
+
+        for address in addresses {
+            self.sync_breakpoint_site(address)?;
+        }
+        self.sync_debug_register(number)
+    }
+
+    /// Resolves `location` and adds a new numbered breakpoint of `kind` covering every
+    /// address it resolved to. Returns the breakpoint's number.
+    pub fn add_breakpoint(
         &mut self,
-        function_name: &str,
-    ) -> Result<u64, Box<dyn std::error::Error>> {
-        let addresses = self.dwinfo.function_addresses(function_name)?;
+        location: Location,
+        kind: BreakpointKind,
+    ) -> Result<usize, Box<dyn std::error::Error>> {
+        // A bare line refers to the file we are currently in, remember which one that was
+        let location = match location {
+            Location::Line { file: None, line } => Location::Line {
+                file: self.current_file()?,
+                line,
+            },
+            location => location,
+        };
+        let addresses = self.resolve_location(&location)?;
 
-        let base_address = self.get_base_address()?;
-        for address in &addresses {
-            self.add_breakpoint_at(*address + base_address)?;
+        let number = self.next_breakpoint_number;
+        self.next_breakpoint_number += 1;
+        self.user_breakpoints
+            .insert(number, UserBreakpoint::new(number, location, kind, addresses.clone()));
+
+        for address in addresses {
+            if let Err(err) = self.sync_breakpoint_site(address) {
+                self.delete_breakpoint(number)?;
+                return Err(err.into());
+            }
+        }
+        if let Err(err) = self.sync_debug_register(number) {
+            self.delete_breakpoint(number)?;
+            return Err(err);
         }
+        Ok(number)
+    }
 
-        Ok(addresses.len() as u64)
+    /// The real addresses a breakpoint location refers to in the running process.
+    pub fn resolve_location(
+        &mut self,
+        location: &Location,
+    ) -> Result<Vec<u64>, Box<dyn std::error::Error>> {
+        let offsets = match location {
+            Location::Address(addr) => return Ok(vec![*addr]),
+            Location::Event(_) => return Ok(Vec::new()),
+            // Past the prologue, where the frame is set up and the arguments are stored
+            Location::Function(function_name) => self
+                .dwinfo
+                .function_addresses(function_name)?
+                .into_iter()
+                .map(|low_pc| self.dwinfo.skip_prologue(low_pc))
+                .collect::<Result<_, _>>()?,
+            Location::Line { file, line } => {
+                self.dwinfo
+                    .line_addresses(file.as_deref(), *line)?
+                    .ok_or("No code found at or after that line")?
+                    .1
+            }
+        };
+        if offsets.is_empty() {
+            return Err(format!("No locations found for \"{location}\"").into());
+        }
+
+        let base_address = self.get_base_address()?;
+        Ok(offsets.into_iter().map(|offset| offset + base_address).collect())
     }
 
-    pub fn continue_process(&mut self) -> Result<(), ptrace::Error> {
-        self.step_over_breakpoint()?;
-        ptrace::cont(self.pid)?;
+    pub fn delete_breakpoint(&mut self, number: usize) -> Result<(), Box<dyn std::error::Error>> {
+        let breakpoint = self
+            .user_breakpoints
+            .remove(&number)
+            .ok_or_else(|| format!("No breakpoint number {number}"))?;
 
-        self.wait_signal();
+        for address in breakpoint.addresses {
+            self.sync_breakpoint_site(address)?;
+        }
+        self.sync_debug_register(number)?;
         Ok(())
     }
 
-    pub fn step_instruction(&mut self) -> Result<(), ptrace::Error> {
-        self.last_step_was_breakpoint = false;
-        todo!()
+    pub fn set_breakpoint_enabled(
+        &mut self,
+        number: usize,
+        enabled: bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let breakpoint = self
+            .user_breakpoints
+            .get_mut(&number)
+            .ok_or_else(|| format!("No breakpoint number {number}"))?;
+        breakpoint.enabled = enabled;
+
+        for address in breakpoint.addresses.clone() {
+            self.sync_breakpoint_site(address)?;
+        }
+        if let Err(err) = self.sync_debug_register(number) {
+            if let Some(breakpoint) = self.user_breakpoints.get_mut(&number) {
+                breakpoint.enabled = false;
+            }
+            return Err(err);
+        }
+        Ok(())
     }
 
-    pub fn step_over_breakpoint(&mut self) -> Result<(), ptrace::Error> {
-        let current_pc = ptrace::get_reg(self.pid, Register::pc())?;
-        dbg!(current_pc);
-        let candidate_breakpoint_addr = current_pc - 1;
+    /// Adds a watchpoint on the `ty.size` bytes at `addr`, backed by a debug
+    /// register. Returns its number.
+    pub fn add_watchpoint(
+        &mut self,
+        addr: u64,
+        ty: TypeInfo,
+        access: WatchAccess,
+    ) -> Result<usize, Box<dyn std::error::Error>> {
+        debugreg::check_watchable(addr, ty.size)?;
+        let mut old_value = vec![0; ty.size as usize];
+        ptrace::peekdata_slice(self.pid, addr, &mut old_value)?;
+
+        let number = self.next_breakpoint_number;
+        self.next_breakpoint_number += 1;
+        let kind = BreakpointKind::Watch { access, ty, old_value };
+        self.user_breakpoints
+            .insert(number, UserBreakpoint::new(number, Location::Address(addr), kind, vec![addr]));
+
+        if let Err(err) = self.sync_debug_register(number) {
+            self.user_breakpoints.remove(&number);
+            return Err(err);
+        }
+        Ok(number)
+    }
+
+    /// Gives every address of breakpoint `number` a debug register exactly when it
+    /// is an enabled hardware breakpoint, and frees its registers otherwise.
+    fn sync_debug_register(&mut self, number: usize) -> Result<(), Box<dyn std::error::Error>> {
+        let (wanted, trigger) = match self.user_breakpoints.get(&number) {
+            Some(breakpoint) if breakpoint.wants_debug_register() => (
+                breakpoint.addresses.clone(),
+                breakpoint.kind.debug_register_trigger(),
+            ),
+            _ => (Vec::new(), None),
+        };
 
-        let Some(bp) = self.breakpoints.get_mut(&candidate_breakpoint_addr) else {
+        for slot in 0..debugreg::NUM_SLOTS {
+            if let Some((owner, addr)) = self.debug_register_slots[slot] {
+                if owner == number && !wanted.contains(&addr) {
+                    for tid in self.thread_ids() {
+                        debugreg::clear_slot(tid, slot)?;
+                    }
+                    self.debug_register_slots[slot] = None;
+                }
+            }
+        }
+
+        let missing: Vec<u64> = wanted
+            .into_iter()
+            .filter(|addr| !self.debug_register_slots.contains(&Some((number, *addr))))
+            .collect();
+        let Some((trigger, len)) = trigger else {
             return Ok(());
         };
 
-        if !bp.enabled() {
+        let free = self.debug_register_slots.iter().filter(|s| s.is_none()).count();
+        if missing.len() > free {
+            return Err(format!(
+                "Breakpoint {number} needs {} hardware debug register(s) but only {free} of {} are free",
+                missing.len(),
+                debugreg::NUM_SLOTS
+            )
+            .into());
+        }
+
+        for addr in missing {
+            let slot = self
+                .debug_register_slots
+                .iter()
+                .position(Option::is_none)
+                .expect("Checked there are enough free slots");
+            // Debug registers are per thread, so every thread gets a copy
+            for tid in self.thread_ids() {
+                debugreg::set_slot(tid, slot, addr, trigger, len)?;
+            }
+            self.debug_register_slots[slot] = Some((number, addr));
+        }
+        Ok(())
+    }
+
+    /// The number of debug registers not used by any hardware breakpoint.
+    pub fn free_debug_registers(&self) -> usize {
+        self.debug_register_slots.iter().filter(|s| s.is_none()).count()
+    }
+
+    /// The debug register holding an enabled hardware execution breakpoint at `addr`.
+    fn hardware_breakpoint_slot_at(&self, addr: u64) -> Option<usize> {
+        self.debug_register_slots.iter().position(|slot| {
+            slot.is_some_and(|(number, slot_addr)| {
+                slot_addr == addr
+                    && self
+                        .user_breakpoints
+                        .get(&number)
+                        .is_some_and(|bp| matches!(bp.kind, BreakpointKind::Hardware))
+            })
+        })
+    }
+
+    /// Makes sure an `INT3` is patched in at `addr` exactly when something wants to
+    /// stop there: an enabled user breakpoint or an internal temporary breakpoint.
+    fn sync_breakpoint_site(&mut self, addr: u64) -> Result<(), ptrace::Error> {
+        let wanted = self.temporary_breakpoints.contains(&addr)
+            || self.user_breakpoints.values().any(|bp| bp.wants(addr));
+
+        if wanted {
+            return self
+                .breakpoints
+                .entry(addr)
+                .or_insert_with(|| Breakpoint::new(self.pid, addr))
+                .enable();
+        }
+
+        match self.breakpoints.remove(&addr) {
+            Some(mut site) if site.enabled() => site.disable(),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn set_breakpoint_condition(
+        &mut self,
+        number: usize,
+        condition: Option<Expr>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.user_breakpoints
+            .get_mut(&number)
+            .ok_or_else(|| format!("No breakpoint number {number}"))?
+            .condition = condition;
+        Ok(())
+    }
+
+    /// Continues until the process stops for a reason the user cares about.
+    /// Breakpoints whose condition is false are stepped over silently.
+    pub fn continue_process(&mut self) -> Result<StopReason, Box<dyn std::error::Error>> {
+        self.ensure_running()?;
+        loop {
+            if let Some(reason) = self.take_held_signal() {
+                return Ok(reason);
+            }
+            if let Some(reason) = self.step_other_threads_over_breakpoints()? {
+                return Ok(reason);
+            }
+            match self.step_over_breakpoint()? {
+                Some(StopReason::SingleStep) | None => {}
+                Some(reason) => return Ok(reason),
+            }
+            self.resume_all()?;
+
+            match self.wait_signal()? {
+                StopReason::Signal(signo, info) => match self.signal_should_stop(signo) {
+                    true => return Ok(StopReason::Signal(signo, info)),
+                    false => continue,
+                },
+                StopReason::Breakpoint(_) => {}
+                StopReason::Exec(program) => match self.catchpoint_hit(&CatchEvent::Exec) {
+                    Some(number) => {
+                        return Ok(StopReason::Catchpoint {
+                            number,
+                            event: format!("exec'd {program}"),
+                        })
+                    }
+                    None => {
+                        println!("process {} is executing new program: {program}", self.pid.0);
+                        continue;
+                    }
+                },
+                StopReason::Syscall => match self.syscall_stop()? {
+                    Some(reason) => return Ok(reason),
+                    None => continue,
+                },
+                reason => return Ok(reason),
+            }
+
+            if let Some(slot) = debugreg::take_triggered_slot(self.tid())? {
+                if let Some((number, _)) = self.debug_register_slots[slot] {
+                    self.last_step_was_breakpoint = false;
+                    let stop = match self.user_breakpoints.get(&number).map(|bp| &bp.kind) {
+                        Some(BreakpointKind::Watch { .. }) => self.watchpoint_stop(number)?,
+                        _ => self
+                            .hit_if_condition_holds(number)
+                            .then(|| StopReason::Breakpoint(vec![number])),
+                    };
+                    if let Some(stop) = stop {
+                        return Ok(stop);
+                    }
+                    continue;
+                }
+            }
+
+            self.last_step_was_breakpoint = self.rewind_breakpoint_trap(self.tid())?;
+            if !self.last_step_was_breakpoint {
+                // An `INT3` we didn't put there, e.g. one compiled into the program
+                if self.signal_should_stop(libc::SIGTRAP) {
+                    return Ok(StopReason::Signal(libc::SIGTRAP, SignalInfo::of(self.tid())?));
+                }
+                continue;
+            }
+            if let Some(numbers) = self.breakpoint_hits()? {
+                return Ok(StopReason::Breakpoint(numbers));
+            }
+        }
+    }
+
+    /// Reports a signal a thread received while another thread's stop was being
+    /// reported, making that thread the current one.
+    fn take_held_signal(&mut self) -> Option<StopReason> {
+        let thread = self.threads.values_mut().find(|thread| thread.held_signal.is_some())?;
+        let info = thread.held_signal.take()?;
+        self.current_thread = thread.number;
+        self.selected_frame = 0;
+        Some(StopReason::Signal(info.signo, info))
+    }
+
+    /// Steps every thread but the current one off the breakpoint it sits on, e.g. a
+    /// hit reported before the user switched threads, so resuming doesn't report it
+    /// again. Threads whose trap was rewound unreported are left to hit it again.
+    /// Returns why a thread stopped if stepping it stopped for something else.
+    fn step_other_threads_over_breakpoints(
+        &mut self,
+    ) -> Result<Option<StopReason>, Box<dyn std::error::Error>> {
+        let current = self.current_thread;
+        let others: Vec<usize> = self
+            .threads
+            .values()
+            .filter(|thread| thread.number != current && !thread.rewound_trap)
+            .map(|thread| thread.number)
+            .collect();
+        for number in others {
+            self.current_thread = number;
+            match self.step_over_breakpoint()? {
+                Some(StopReason::SingleStep) | None => {}
+                Some(reason) => return Ok(Some(reason)),
+            }
+        }
+        self.current_thread = current;
+        Ok(None)
+    }
+
+    /// Decides whether the current thread's system call stop should stop, describing
+    /// the call with its arguments on entry and its return value on exit.
+    fn syscall_stop(&mut self) -> Result<Option<StopReason>, ptrace::Error> {
+        let thread = self.current_thread_mut();
+        thread.in_syscall = !thread.in_syscall;
+        let entry = thread.in_syscall;
+
+        let number = ptrace::get_reg(self.tid(), Register::ORIGRAX)?;
+        let candidates: Vec<usize> = self
+            .user_breakpoints
+            .values()
+            .filter(|bp| bp.enabled)
+            .filter(|bp| matches!(&bp.location, Location::Event(event) if event.catches_syscall(number)))
+            .map(|bp| bp.number)
+            .collect();
+        let hits: Vec<usize> = candidates
+            .into_iter()
+            .filter(|&number| self.hit_if_condition_holds(number))
+            .collect();
+        let Some(&catchpoint) = hits.first() else {
+            return Ok(None);
+        };
+
+        let name = syscall_name(number);
+        let event = match entry {
+            true => {
+                let args = [
+                    Register::RDI,
+                    Register::RSI,
+                    Register::RDX,
+                    Register::R10,
+                    Register::R8,
+                    Register::R9,
+                ]
+                .into_iter()
+                .map(|reg| Ok(format!("0x{:x}", ptrace::get_reg(self.tid(), reg)?)))
+                .collect::<Result<Vec<_>, ptrace::Error>>()?;
+                format!("call to syscall {name}({})", args.join(", "))
+            }
+            false => {
+                let value = ptrace::get_reg(self.tid(), Register::RAX)? as i64;
+                format!("returned from syscall {name} = {value}")
+            }
+        };
+        Ok(Some(StopReason::Catchpoint {
+            number: catchpoint,
+            event,
+        }))
+    }
+
+    /// The first enabled catchpoint for `event` whose condition holds, counting a hit
+    /// for each one that does.
+    fn catchpoint_hit(&mut self, event: &CatchEvent) -> Option<usize> {
+        let location = Location::Event(event.clone());
+        let candidates: Vec<usize> = self
+            .user_breakpoints
+            .values()
+            .filter(|bp| bp.enabled && bp.location == location)
+            .map(|bp| bp.number)
+            .collect();
+        let hits: Vec<usize> = candidates
+            .into_iter()
+            .filter(|&number| self.hit_if_condition_holds(number))
+            .collect();
+        hits.first().copied()
+    }
+
+    /// Decides whether the breakpoint trap at the current PC should stop, counting
+    /// a hit for every user breakpoint there whose condition holds. Returns the
+    /// numbers of those breakpoints, or `None` if the process should keep going.
+    /// Internal temporary breakpoints always stop.
+    fn breakpoint_hits(&mut self) -> Result<Option<Vec<usize>>, ptrace::Error> {
+        let pc = ptrace::get_reg(self.tid(), Register::pc())?;
+
+        let candidates: Vec<usize> = self
+            .user_breakpoints
+            .values()
+            .filter(|bp| bp.wants(pc))
+            .map(|bp| bp.number)
+            .collect();
+        let hits: Vec<usize> = candidates
+            .into_iter()
+            .filter(|number| self.hit_if_condition_holds(*number))
+            .collect();
+
+        let should_stop = !hits.is_empty() || self.temporary_breakpoints.contains(&pc);
+        Ok(should_stop.then_some(hits))
+    }
+
+    /// Counts a hit for breakpoint `number` if its condition holds.
+    fn hit_if_condition_holds(&mut self, number: usize) -> bool {
+        if !self.condition_holds(number) {
+            return false;
+        }
+        if let Some(bp) = self.user_breakpoints.get_mut(&number) {
+            bp.hit_count += 1;
+        }
+        true
+    }
+
+    /// Decides whether the watchpoint `number` that just triggered should stop,
+    /// returning its old and new value if so.
+    fn watchpoint_stop(&mut self, number: usize) -> Result<Option<StopReason>, ptrace::Error> {
+        let Some(breakpoint) = self.user_breakpoints.get_mut(&number) else {
+            return Ok(None);
+        };
+        let addr = breakpoint.addresses[0];
+        let BreakpointKind::Watch { access, ty, old_value } = &mut breakpoint.kind else {
+            return Ok(None);
+        };
+
+        let mut new_value = vec![0; old_value.len()];
+        ptrace::peekdata_slice(self.pid, addr, &mut new_value)?;
+        let changed = new_value != *old_value;
+        let report = match access {
+            WatchAccess::Write => changed,
+            WatchAccess::Read => !changed,
+            WatchAccess::Access => true,
+        };
+
+        let old = TypedValue {
+            ty: ty.clone(),
+            bytes: std::mem::replace(old_value, new_value.clone()),
+        };
+        let new = TypedValue {
+            ty: ty.clone(),
+            bytes: new_value,
+        };
+
+        if !report || !self.condition_holds(number) {
+            return Ok(None);
+        }
+        if let Some(breakpoint) = self.user_breakpoints.get_mut(&number) {
+            breakpoint.hit_count += 1;
+        }
+        Ok(Some(StopReason::Watchpoint { number, old, new }))
+    }
+
+    /// Evaluates the condition of breakpoint `number`. Breakpoints without one always
+    /// hold, and so do ones whose condition can't be evaluated so the user gets to see why.
+    fn condition_holds(&mut self, number: usize) -> bool {
+        let Some(condition) = self
+            .user_breakpoints
+            .get(&number)
+            .and_then(|bp| bp.condition.clone())
+        else {
+            return true;
+        };
+
+        match condition.evaluate(self) {
+            Ok(value) => value != 0,
+            Err(e) => {
+                println!("Error in condition of breakpoint {number}: {e}");
+                true
+            }
+        }
+    }
+
+    /// Executes exactly one machine instruction. If the PC is sitting on an enabled
+    /// breakpoint, the original instruction is executed instead of the `INT3`.
+    pub fn step_instruction(&mut self) -> Result<StopReason, Box<dyn std::error::Error>> {
+        self.ensure_running()?;
+        if let Some(reason) = self.step_over_breakpoint()? {
+            return Ok(reason);
+        }
+        self.single_step()
+    }
+
+    /// Single-steps, delivering any pending signal first. Signals that arrive and
+    /// aren't set to stop are passed on according to their policy and the step is
+    /// retried.
+    fn single_step(&mut self) -> Result<StopReason, Box<dyn std::error::Error>> {
+        loop {
+            self.stepping_thread = Some(self.current_thread);
+            self.resume_thread(self.current_thread)?;
+            match self.wait_signal()? {
+                StopReason::Signal(signo, info) => {
+                    if self.signal_should_stop(signo) {
+                        return Ok(StopReason::Signal(signo, info));
+                    }
+                }
+                StopReason::SingleStep => {
+                    let stop = self.stepped_watchpoint_stop()?;
+                    return Ok(stop.unwrap_or(StopReason::SingleStep));
+                }
+                reason => return Ok(reason),
+            }
+        }
+    }
+
+    /// Checks whether a watchpoint triggered during the single step the current
+    /// thread just took, which the kernel reports as an ordinary step. Reading DR6
+    /// clears it, so the hit isn't mistaken for a later one. Returns the watchpoint
+    /// stop if it should be reported.
+    fn stepped_watchpoint_stop(&mut self) -> Result<Option<StopReason>, ptrace::Error> {
+        if self.debug_register_slots.iter().all(Option::is_none) {
+            return Ok(None);
+        }
+        let Some(slot) = debugreg::take_triggered_slot(self.tid())? else {
+            return Ok(None);
+        };
+        let Some((number, _)) = self.debug_register_slots[slot] else {
+            return Ok(None);
+        };
+        match self.user_breakpoints.get(&number).map(|bp| &bp.kind) {
+            Some(BreakpointKind::Watch { .. }) => self.watchpoint_stop(number),
+            _ => Ok(None),
+        }
+    }
+
+    /// Applies the policy for `signo`, which the current thread just received.
+    /// Returns whether to stop.
+    fn signal_should_stop(&mut self, signo: i32) -> bool {
+        self.apply_signal_policy(self.current_thread, signo)
+    }
+
+    /// Applies the policy for `signo`, which thread `number` just received: queues it
+    /// to be passed on and reports it if it won't stop. Returns whether to stop.
+    fn apply_signal_policy(&mut self, number: usize, signo: i32) -> bool {
+        let policy = self.signals.get(signo);
+        if policy.pass {
+            if let Some(thread) = self.threads.get_mut(&number) {
+                thread.pending_signal = Some(signo);
+            }
+        }
+        if policy.print && !policy.stop {
+            let action = if policy.pass { "passing it on" } else { "ignoring it" };
+            println!("Program received {}, {action}", signal_name(signo));
+        }
+        policy.stop
+    }
+
+    /// The thread id of the current thread.
+    pub fn tid(&self) -> Pid {
+        self.threads[&self.current_thread].tid
+    }
+
+    pub fn current_thread_mut(&mut self) -> &mut Thread {
+        self.threads.get_mut(&self.current_thread).expect("The current thread exists")
+    }
+
+    fn thread_ids(&self) -> Vec<Pid> {
+        self.threads.values().map(|thread| thread.tid).collect()
+    }
+
+    fn thread_number(&self, tid: Pid) -> Option<usize> {
+        self.threads.values().find(|thread| thread.tid == tid).map(|thread| thread.number)
+    }
+
+    /// Makes thread `number` the one registers are read from and stepping applies to.
+    pub fn select_thread(&mut self, number: usize) -> Result<(), String> {
+        if !self.threads.contains_key(&number) {
+            return Err(format!("No thread number {number}"));
+        }
+        self.current_thread = number;
+        self.reported_thread = number;
+        self.selected_frame = 0;
+        Ok(())
+    }
+
+    /// The current thread, if it changed since the last time this was asked.
+    pub fn take_thread_switch(&mut self) -> Option<&Thread> {
+        if self.reported_thread == self.current_thread {
+            return None;
+        }
+        self.reported_thread = self.current_thread;
+        self.threads.get(&self.current_thread)
+    }
+
+    /// Starts tracking a new, stopped thread, giving it the debug registers every
+    /// other thread has.
+    pub fn add_thread(&mut self, tid: Pid) -> Result<usize, Box<dyn std::error::Error>> {
+        let number = self.next_thread_number;
+        self.next_thread_number += 1;
+        self.threads.insert(number, Thread::new(number, tid));
+        self.copy_debug_registers(tid)?;
+        println!("[New thread {number} ({})]", tid.0);
+        Ok(number)
+    }
+
+    /// Programs thread `tid` with the debug registers every tracked thread has.
+    fn copy_debug_registers(&self, tid: Pid) -> Result<(), Box<dyn std::error::Error>> {
+        for (slot, owner) in self.debug_register_slots.iter().enumerate() {
+            let Some((owner, addr)) = owner else {
+                continue;
+            };
+            let trigger = self
+                .user_breakpoints
+                .get(owner)
+                .and_then(|bp| bp.kind.debug_register_trigger());
+            if let Some((trigger, len)) = trigger {
+                debugreg::set_slot(tid, slot, *addr, trigger, len)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn remove_thread(&mut self, number: usize) {
+        if let Some(thread) = self.threads.remove(&number) {
+            println!("[Thread {number} ({}) exited]", thread.tid.0);
+        }
+        if self.current_thread == number {
+            self.current_thread = self.threads.keys().next().copied().unwrap_or(1);
+        }
+    }
+
+    /// Lets every thread run.
+    fn resume_all(&mut self) -> Result<(), ptrace::Error> {
+        self.stepping_thread = None;
+        let numbers: Vec<usize> = self.threads.keys().copied().collect();
+        for number in numbers {
+            self.resume_thread(number)?;
+        }
+        Ok(())
+    }
+
+    /// Resumes thread `number` the way the current operation wants: the stepping
+    /// thread takes a single step, and every thread runs when none is stepping.
+    /// Otherwise the thread stays stopped.
+    fn resume_thread(&mut self, number: usize) -> Result<(), ptrace::Error> {
+        let stepping = self.stepping_thread;
+        self.selected_frame = 0;
+        let catches_syscalls = self.user_breakpoints.values().any(|bp| {
+            bp.enabled && matches!(bp.location, Location::Event(CatchEvent::Syscall(_)))
+        });
+        let Some(thread) = self.threads.get_mut(&number) else {
             return Ok(());
+        };
+        if thread.running {
+            return Ok(());
+        }
+        let signal = thread.pending_signal.unwrap_or(0);
+        match stepping {
+            Some(stepping) if stepping == number => ptrace::single_step(thread.tid, signal)?,
+            Some(_) => return Ok(()),
+            None if catches_syscalls => ptrace::syscall(thread.tid, signal)?,
+            None => ptrace::cont(thread.tid, signal)?,
         }
+        // Only `PTRACE_SYSCALL` stops again when the system call returns
+        if stepping.is_some() || !catches_syscalls {
+            thread.in_syscall = false;
+        }
+        thread.pending_signal = None;
+        thread.rewound_trap = false;
+        thread.running = true;
+        Ok(())
+    }
 
-        // Go back to the where the INT3 breakpoint was and restore it.
-        ptrace::set_reg(self.pid, Register::pc(), candidate_breakpoint_addr)?;
-        bp.disable()?;
-        ptrace::single_step(self.pid)?;
+    /// Stops every thread still running, so the whole process holds still while the
+    /// user looks at it. A thread that stops for something else first keeps that
+    /// stop as best it can: a breakpoint trap is rewound to be hit again, and a
+    /// signal gets its policy applied, with signals set to stop held to be reported
+    /// before the process runs again.
+    fn stop_other_threads(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let running: Vec<(usize, Pid)> = self
+            .threads
+            .values()
+            .filter(|thread| thread.running)
+            .map(|thread| (thread.number, thread.tid))
+            .collect();
+        for (_, tid) in &running {
+            tgkill(self.pid, *tid, libc::SIGSTOP)?;
+        }
 
-        self.wait_signal();
+        for (number, tid) in running {
+            let status = wait_for_signal(tid);
+            if libc::WIFEXITED(status) || libc::WIFSIGNALED(status) {
+                self.remove_thread(number);
+                continue;
+            }
+            let thread = self.threads.get_mut(&number).expect("Thread exists");
+            thread.running = false;
 
-        self.breakpoints
-            .get_mut(&candidate_breakpoint_addr)
-            .expect("Will exist. Relooking up because of XOR lifetimes. TODO")
-            .enable()?;
+            let signo = libc::WSTOPSIG(status);
+            let event = status >> 16;
+            if signo == libc::SIGSTOP && event == 0 {
+                continue;
+            }
+            thread.expecting_sigstop = true;
+            if signo == libc::SIGTRAP | 0x80 {
+                // The system call stop itself is lost, but has to be counted
+                thread.in_syscall = !thread.in_syscall;
+                continue;
+            }
 
+            if event == libc::PTRACE_EVENT_CLONE {
+                self.track_cloned_thread(tid)?;
+            } else if signo == libc::SIGTRAP {
+                let rewound = self.rewind_breakpoint_trap(tid)?;
+                self.threads.get_mut(&number).expect("Thread exists").rewound_trap = rewound;
+            } else if self.apply_signal_policy(number, signo) {
+                let held_signal = SignalInfo::of(tid)?;
+                self.threads.get_mut(&number).expect("Thread exists").held_signal = Some(held_signal);
+            }
+        }
         Ok(())
     }
 
+    /// Handles thread `number` forking a child, which starts out traced and with a copy
+    /// of every `INT3`. The process `follow_fork_mode` doesn't pick gets our
+    /// breakpoints taken out and is detached. Returns the number of the thread that
+    /// carries on.
+    ///
+    /// A `vfork` child borrows the parent's memory until it calls `exec` or exits, so
+    /// the one copy of the `INT3`s is taken out for that long. When following such a
+    /// child, breakpoints are only put back once it calls `exec`.
+    fn follow_fork(
+        &mut self,
+        number: usize,
+        vfork: bool,
+    ) -> Result<usize, Box<dyn std::error::Error>> {
+        let tid = self.threads[&number].tid;
+        let child = Pid(ptrace::get_event_message(tid)? as i32);
+        if !self.stopped_new_processes.remove(&child) {
+            // A new process always reports a `SIGSTOP` first
+            wait_for_signal(child);
+        }
+        if vfork {
+            for site in self.breakpoints.values_mut().filter(|site| site.enabled()) {
+                site.disable()?;
+            }
+        }
+
+        if self.follow_fork_mode == FollowForkMode::Parent {
+            for site in self.breakpoints.values().filter(|site| site.enabled()) {
+                site.remove_from(child)?;
+            }
+            ptrace::detach(child, 0)?;
+            println!("[Detaching after fork from child process {}]", child.0);
+            return Ok(number);
+        }
+
+        println!("[Attaching after process {} fork to child process {}]", self.pid.0, child.0);
+        self.stop_other_threads()?;
+        for site in self.breakpoints.values_mut() {
+            if site.enabled() {
+                site.remove_from(self.pid)?;
+            }
+            site.set_pid(child);
+        }
+        for tid in self.thread_ids() {
+            for (slot, owner) in self.debug_register_slots.iter().enumerate() {
+                if owner.is_some() {
+                    debugreg::clear_slot(tid, slot)?;
+                }
+            }
+            ptrace::detach(tid, 0)?;
+        }
+
+        self.pid = child;
+        self.threads = BTreeMap::from([(1, Thread::new(1, child))]);
+        self.next_thread_number = 2;
+        self.current_thread = 1;
+        self.stepping_thread = self.stepping_thread.map(|_| 1);
+        self.copy_debug_registers(child)?;
+        Ok(1)
+    }
+
+    /// Picks the process up again after `exec` replaced its program, which took our
+    /// `INT3`s, debug registers and every other thread with it. The debug info is
+    /// read from the new executable and every breakpoint resolved against it.
+    /// Returns the path of the new program.
+    fn reload_after_exec(&mut self, tid: Pid) -> Result<String, Box<dyn std::error::Error>> {
+        // The thread that called `exec` takes over the main thread's id. Every other
+        // thread is gone, but still has its exit to report.
+        let former_tid = Pid(ptrace::get_event_message(tid)? as i32);
+        for thread in self.threads.values() {
+            if thread.tid != self.pid && thread.tid != former_tid {
+                wait_for_signal(thread.tid);
+            }
+        }
+        self.threads = BTreeMap::from([(1, Thread::new(1, self.pid))]);
+        self.current_thread = 1;
+        self.stepping_thread = None;
+
+        let program = std::fs::read_link(format!("/proc/{}/exe", self.pid.0))?
+            .to_string_lossy()
+            .into_owned();
+        self.dwinfo = DwarfInfo::load(&program)?;
+        self.dwinfo_from_exec = true;
+        self.clear_base_address();
+        self.breakpoints.clear();
+        self.temporary_breakpoints.clear();
+        self.debug_register_slots = [None; debugreg::NUM_SLOTS];
+        self.last_step_was_breakpoint = false;
+        self.reresolve_breakpoints();
+        Ok(program)
+    }
+
+    /// Starts tracking the thread that `tid` just created in a `PTRACE_EVENT_CLONE`
+    /// stop, unless its first stop already announced it. Returns its number.
+    fn track_cloned_thread(&mut self, tid: Pid) -> Result<usize, Box<dyn std::error::Error>> {
+        let new_tid = Pid(ptrace::get_event_message(tid)? as i32);
+        if let Some(number) = self.thread_number(new_tid) {
+            return Ok(number);
+        }
+        // A new thread always reports a `SIGSTOP` first
+        wait_for_signal(new_tid);
+        self.add_thread(new_tid)
+    }
+
+    /// After an `INT3` traps, the PC is one past the breakpoint address. Move thread
+    /// `tid` back onto the breakpoint so its PC always points at the next instruction
+    /// to execute. Returns whether the trap was one of our breakpoints.
+    fn rewind_breakpoint_trap(&self, tid: Pid) -> Result<bool, ptrace::Error> {
+        let current_pc = ptrace::get_reg(tid, Register::pc())?;
+        let candidate_breakpoint_addr = current_pc - 1;
+
+        let is_breakpoint = self
+            .breakpoints
+            .get(&candidate_breakpoint_addr)
+            .is_some_and(Breakpoint::enabled);
+
+        if is_breakpoint {
+            ptrace::set_reg(tid, Register::pc(), candidate_breakpoint_addr)?;
+        }
+        Ok(is_breakpoint)
+    }
+
+    /// Steps over the breakpoint at the current PC, executing the original instruction
+    /// and then re-arming the `INT3` or debug register. Returns why the step stopped,
+    /// or `None` if there was no breakpoint to step over.
+    pub fn step_over_breakpoint(
+        &mut self,
+    ) -> Result<Option<StopReason>, Box<dyn std::error::Error>> {
+        let current_pc = ptrace::get_reg(self.tid(), Register::pc())?;
+        self.last_step_was_breakpoint = false;
+
+        let site_enabled = self
+            .breakpoints
+            .get(&current_pc)
+            .is_some_and(Breakpoint::enabled);
+        let hardware_slot = self.hardware_breakpoint_slot_at(current_pc);
+
+        if !site_enabled && hardware_slot.is_none() {
+            return Ok(None);
+        }
+
+        if let Some(bp) = self.breakpoints.get_mut(&current_pc).filter(|_| site_enabled) {
+            bp.disable()?;
+        }
+        if let Some(slot) = hardware_slot {
+            debugreg::set_slot_enabled(self.tid(), slot, false)?;
+        }
+
+        let reason = self.single_step()?;
+        if reason.is_exit() {
+            return Ok(Some(reason));
+        }
+
+        if site_enabled {
+            self.breakpoints
+                .get_mut(&current_pc)
+                .expect("Will exist. Relooking up because of XOR lifetimes. TODO")
+                .enable()?;
+        }
+        if let Some(slot) = hardware_slot {
+            debugreg::set_slot_enabled(self.tid(), slot, true)?;
+        }
+
+        Ok(Some(reason))
+    }
+
+    /// Single-steps until execution reaches the start of a different source line.
+    /// Calls into functions with line info (including inlined subroutines) are
+    /// stepped into, calls into functions without line info are run to completion.
+    pub fn step_line(&mut self) -> Result<StopReason, Box<dyn std::error::Error>> {
+        self.step_source(false)
+    }
+
+    /// Like [`Target::step_line`], but every call made from the current line is run
+    /// to completion instead of being stepped into.
+    pub fn next_line(&mut self) -> Result<StopReason, Box<dyn std::error::Error>> {
+        self.step_source(true)
+    }
+
+    fn step_source(&mut self, step_over_calls: bool) -> Result<StopReason, Box<dyn std::error::Error>> {
+        let base = self.get_base_address()?;
+        let start_line = self.current_code_point()?.map(|cp| cp.line_key());
+
+        loop {
+            let pc_before = ptrace::get_reg(self.tid(), Register::pc())?;
+            let sp_before = ptrace::get_reg(self.tid(), Register::RSP)?;
+            let reason = self.step_instruction()?;
+            if !matches!(reason, StopReason::SingleStep) {
+                return Ok(reason);
+            }
+
+            if step_over_calls {
+                if let Some(return_address) = self.stepped_into_call(pc_before, sp_before)? {
+                    if let Some(reason) = self.run_until_return(return_address, sp_before)? {
+                        return Ok(reason);
+                    }
+                }
+            }
+
+            let mut code_point = self.current_code_point()?;
+            if code_point.is_none() {
+                // No line info here. If we just called into it, let it run back out.
+                let Some(return_address) = self.stepped_into_call(pc_before, sp_before)? else {
+                    return Ok(reason);
+                };
+                if let Some(reason) = self.run_until_return(return_address, sp_before)? {
+                    return Ok(reason);
+                }
+                code_point = self.current_code_point()?;
+            }
+            let Some(cp) = code_point else {
+                return Ok(reason);
+            };
+            if !step_over_calls && self.stepped_into_call(pc_before, sp_before)?.is_some() {
+                return self.run_past_prologue();
+            }
+
+            if !cp.is_statement_start(base) {
+                continue;
+            }
+            if cp.is_function_entry() || start_line.as_ref() != Some(&cp.line_key()) {
+                return Ok(reason);
+            }
+        }
+    }
+
+    /// Runs from the entry of the function just called to the end of its prologue,
+    /// where its frame is set up and its first line begins, as breaking there would.
+    fn run_past_prologue(&mut self) -> Result<StopReason, Box<dyn std::error::Error>> {
+        let pc = ptrace::get_reg(self.tid(), Register::pc())?;
+        let base = self.get_base_address()?;
+        let body = self.dwinfo.skip_prologue(pc - base)? + base;
+        if body == pc {
+            return Ok(StopReason::SingleStep);
+        }
+        match self.run_to_address(body)? {
+            StopReason::Breakpoint(numbers) if numbers.is_empty() => Ok(StopReason::SingleStep),
+            reason => Ok(reason),
+        }
+    }
+
+    /// Runs until the current function returns to its caller. Returns why the process
+    /// stopped along with the returned value, or why it couldn't be read. The value
+    /// is `None` if the function is unknown or something else stopped the process
+    /// first.
+    pub fn finish(
+        &mut self,
+    ) -> Result<(StopReason, Option<ReturnedValue>), Box<dyn std::error::Error>> {
+        self.ensure_running()?;
+        let pc = ptrace::get_reg(self.tid(), Register::pc())?;
+        let base = self.get_base_address()?;
+        let subprogram = find_subprogram_at_pc(&self.dwinfo.dwarf, pc, base)?;
+        let functions = find_inline_frames_at_pc(&self.dwinfo.dwarf, pc, base)?;
+        if let Some(inlined) = functions.first().filter(|function| function.inlined) {
+            return Err(format!(
+                "\"finish\" can't return from {}, which was inlined and has no frame of its \
+                 own. Use \"next\" to step out of it.",
+                inlined.summary()
+            )
+            .into());
+        }
+
+        // The caller resumes at the return address with its stack pointer at our CFA.
+        // Returns from deeper recursive calls hit the same address further down.
+        let frames = self.backtrace()?;
+        let cfa = frames[0].cfa.ok_or("Cannot find the caller of the current function")?;
+        let caller = frames.get(1).ok_or("\"finish\" not meaningful in the outermost frame.")?;
+        if let Some(reason) = self.run_until_return(caller.pc, cfa)? {
+            return Ok((reason, None));
+        }
+
+        let value = subprogram.map(|subprogram| {
+            self.read_return_value(subprogram.return_type, &subprogram.return_location)
+        });
+        Ok((StopReason::Breakpoint(Vec::new()), value.transpose()?))
+    }
+
+    /// Reads a just-returned value of type `ty` following the System V calling
+    /// convention: each eightbyte of a small value in RAX:RDX or XMM0:XMM1 by its
+    /// class, and anything larger in memory pointed to by RAX.
+    fn read_return_value(
+        &self,
+        ty: TypeInfo,
+        location: &ReturnLocation,
+    ) -> Result<ReturnedValue, ptrace::Error> {
+        let size = ty.size as usize;
+        let regs = ptrace::get_regs(self.tid())?;
+
+        let bytes = match location {
+            ReturnLocation::Registers(classes) => {
+                let fpregs = ptrace::get_fpregs(self.tid())?;
+                // The low eightbyte of XMM0 and XMM1, each 4 words in `xmm_space`
+                let xmm = |n: usize| {
+                    let words = &fpregs.xmm_space[n * 4..n * 4 + 2];
+                    (words[1] as u64) << 32 | words[0] as u64
+                };
+                let mut integer = [regs.rax, regs.rdx].into_iter();
+                let mut sse = [xmm(0), xmm(1)].into_iter();
+                let mut bytes: Vec<u8> = classes
+                    .iter()
+                    .flat_map(|class| {
+                        let word = match class {
+                            Some(EightbyteClass::Integer) => integer.next(),
+                            Some(EightbyteClass::Sse) => sse.next(),
+                            None => None,
+                        };
+                        word.unwrap_or(0).to_le_bytes()
+                    })
+                    .collect();
+                bytes.truncate(size);
+                bytes
+            }
+            ReturnLocation::Memory => {
+                let mut bytes = vec![0; size];
+                ptrace::peekdata_slice(self.pid, regs.rax, &mut bytes)?;
+                bytes
+            }
+            ReturnLocation::Unsupported => {
+                return Ok(Err(format!("can't read a returned {}", ty.name)));
+            }
+        };
+
+        Ok(Ok(TypedValue { ty, bytes }))
+    }
+
+    /// If the instruction at `pc_before` that was just stepped was a `call`, returns
+    /// the return address it pushed.
+    fn stepped_into_call(
+        &self,
+        pc_before: u64,
+        sp_before: u64,
+    ) -> Result<Option<u64>, ptrace::Error> {
+        let sp = ptrace::get_reg(self.tid(), Register::RSP)?;
+        if sp != sp_before.wrapping_sub(8) {
+            return Ok(None);
+        }
+
+        // x86 instructions are at most 15 bytes, so the pushed value must be just
+        // past the instruction we stepped.
+        let pushed = ptrace::peekdata_as::<u64>(self.pid, sp)?;
+        let call_range = (pc_before + 1)..=(pc_before + 15);
+        Ok(call_range.contains(&pushed).then_some(pushed))
+    }
+
+    /// Continues until execution reaches `addr` or something else stops the process,
+    /// using a temporary breakpoint at `addr` for the duration.
+    pub fn run_to_address(&mut self, addr: u64) -> Result<StopReason, Box<dyn std::error::Error>> {
+        let temporary = self.temporary_breakpoints.insert(addr);
+        self.sync_breakpoint_site(addr)?;
+
+        let thread = self.current_thread;
+        let reason = loop {
+            match self.continue_process() {
+                // Another thread ran into the temporary breakpoint
+                Ok(StopReason::Breakpoint(numbers))
+                    if numbers.is_empty() && self.current_thread != thread => {}
+                reason => break reason,
+            }
+        };
+
+        if temporary {
+            self.temporary_breakpoints.remove(&addr);
+            if !self.exited {
+                self.sync_breakpoint_site(addr)?;
+            }
+            if !self.breakpoints.contains_key(&addr) {
+                self.last_step_was_breakpoint = false;
+            }
+        }
+        reason
+    }
+
+    /// Continues to the first line of `main`, past its prologue.
+    pub fn run_to_main(&mut self) -> Result<StopReason, Box<dyn std::error::Error>> {
+        let main = *self
+            .dwinfo
+            .function_addresses("main")?
+            .first()
+            .ok_or("No function \"main\" to stop at")?;
+        let body = self.dwinfo.skip_prologue(main)?;
+        let base = self.get_base_address()?;
+        self.run_to_address(body + base)
+    }
+
+    /// The real address of the program's ELF entry point.
+    pub fn entry_point(&mut self) -> Result<u64, Box<dyn std::error::Error>> {
+        Ok(self.dwinfo.entry_point + self.get_base_address()?)
+    }
+
+    /// Continues until execution comes back to `return_address` in a frame whose
+    /// stack pointer is at or above `frame_sp`. A temporary breakpoint is placed at
+    /// the return address for the duration. Returns why the process stopped if
+    /// something else (e.g. a user breakpoint) stopped it first.
+    fn run_until_return(
+        &mut self,
+        return_address: u64,
+        frame_sp: u64,
+    ) -> Result<Option<StopReason>, Box<dyn std::error::Error>> {
+        let temporary = self.temporary_breakpoints.insert(return_address);
+        self.sync_breakpoint_site(return_address)?;
+
+        let thread = self.current_thread;
+        let interrupted = loop {
+            let reason = self.continue_process()?;
+            if reason.is_exit() {
+                break Some(reason);
+            }
+            let temporary_hit = matches!(&reason, StopReason::Breakpoint(numbers) if numbers.is_empty());
+            if temporary_hit && self.current_thread != thread {
+                // Another thread ran into the temporary breakpoint
+                continue;
+            }
+            let pc = ptrace::get_reg(self.tid(), Register::pc())?;
+            let StopReason::Breakpoint(numbers) = &reason else {
+                break Some(reason);
+            };
+            if pc != return_address {
+                break Some(reason);
+            }
+            // A deeper recursive call can hit the same return address first.
+            if ptrace::get_reg(self.tid(), Register::RSP)? >= frame_sp {
+                break None;
+            }
+            if !numbers.is_empty() {
+                break Some(reason);
+            }
+        };
+
+        if temporary {
+            self.temporary_breakpoints.remove(&return_address);
+            if !self.exited {
+                self.sync_breakpoint_site(return_address)?;
+            }
+            // The trap may have come from the temporary breakpoint, which is now gone
+            if !self.breakpoints.contains_key(&return_address) {
+                self.last_step_was_breakpoint = false;
+            }
+        }
+        Ok(interrupted)
+    }
+
+    /// The source file of the current PC, if there is debug info for it.
+    fn current_file(&mut self) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        Ok(self
+            .current_code_point()?
+            .and_then(|cp| cp.file)
+            .map(|file| file.to_string_lossy().into_owned()))
+    }
+
+    /// The call stack of the current thread, innermost frame first.
+    pub fn backtrace(&mut self) -> Result<Vec<Frame>, Box<dyn std::error::Error>> {
+        self.ensure_running()?;
+        let registers = FrameRegisters::from_user_regs(&ptrace::get_regs(self.tid())?);
+        let base = self.get_base_address()?;
+
+        let mappings = self.mapped_files()?;
+        for path in mappings.keys() {
+            if !self.library_cfi.contains_key(path) && mappings[path].0 != base {
+                // Without CFI for a library, unwinding through it falls back to `rbp`
+                let cfi = CallFrameInfo::read(path).unwrap_or_else(|_| CallFrameInfo::empty());
+                self.library_cfi.insert(path.clone(), cfi);
+            }
+        }
+        let modules: Vec<Module> = mappings
+            .iter()
+            .map(|(path, (file_base, end))| Module {
+                range: *file_base..*end,
+                base: *file_base,
+                cfi: match *file_base == base {
+                    true => &self.dwinfo.cfi,
+                    false => &self.library_cfi[path],
+                },
+            })
+            .collect();
+
+        let pid = self.pid;
+        Ok(unwind(&modules, registers, |addr| ptrace::peekdata_as::<u64>(pid, addr).ok()))
+    }
+
+    /// The call stack of the current thread as the user sees it, innermost first:
+    /// each function inlined into a real frame gets a frame of its own.
+    pub fn stack_frames(&mut self) -> Result<Vec<StackFrame>, Box<dyn std::error::Error>> {
+        let frames = self.backtrace()?;
+        let base = self.get_base_address()?;
+        let mut stack = Vec::new();
+        for (real_level, frame) in frames.into_iter().enumerate() {
+            // A caller's PC is the return address, which can be on the next line
+            let lookup = if real_level == 0 { frame.pc } else { frame.pc - 1 };
+            let functions = find_inline_frames_at_pc(&self.dwinfo.dwarf, lookup, base)?;
+            if functions.is_empty() {
+                stack.push(StackFrame { frame, real_level, function: None });
+                continue;
+            }
+            for function in functions {
+                stack.push(StackFrame { frame: frame.clone(), real_level, function: Some(function) });
+            }
+        }
+        Ok(stack)
+    }
+
+    /// The level of the selected stack frame.
+    pub fn selected_frame(&self) -> usize {
+        self.selected_frame
+    }
+
+    /// Makes the frame at `level` the one registers are read from. Returns the frame,
+    /// or `None` if the stack has no frame at `level`.
+    pub fn select_frame(
+        &mut self,
+        level: usize,
+    ) -> Result<Option<StackFrame>, Box<dyn std::error::Error>> {
+        let mut stack = self.stack_frames()?;
+        if level >= stack.len() {
+            return Ok(None);
+        }
+        self.selected_frame = level;
+        Ok(Some(stack.swap_remove(level)))
+    }
+
+    /// The value of `reg` in the selected frame. Frames further out only know the
+    /// registers unwinding recovered, such as the callee-saved ones.
+    pub fn frame_register(&mut self, reg: Register) -> Result<u64, Box<dyn std::error::Error>> {
+        if self.selected_frame == 0 {
+            return Ok(ptrace::get_reg(self.tid(), reg)?);
+        }
+        let (frame, _) = self.selected_stack_frame()?;
+        self.register_in(&frame, reg)
+    }
+
+    /// The selected frame, and how many functions inlined into the same real frame
+    /// are further in.
+    fn selected_stack_frame(&mut self) -> Result<(StackFrame, usize), Box<dyn std::error::Error>> {
+        let level = self.selected_frame;
+        let mut stack = self.stack_frames()?;
+        if level >= stack.len() {
+            return Err("The selected frame no longer exists".into());
+        }
+        let real_level = stack[level].real_level;
+        let inline_depth = stack[..level].iter().filter(|frame| frame.real_level == real_level).count();
+        Ok((stack.swap_remove(level), inline_depth))
+    }
+
+    /// The value of `reg` in `frame`.
+    fn register_in(&self, frame: &StackFrame, reg: Register) -> Result<u64, Box<dyn std::error::Error>> {
+        // Functions inlined into the innermost frame run with the live registers
+        if frame.real_level == 0 {
+            return Ok(ptrace::get_reg(self.tid(), reg)?);
+        }
+        Ok(frame
+            .frame
+            .registers
+            .get(reg)
+            .ok_or_else(|| format!("{reg:?} was not saved in this frame"))?)
+    }
+
+    /// The call stack and the variables in scope in its selected frame, innermost
+    /// scope first.
+    fn frame_scope(
+        &mut self,
+    ) -> Result<(Vec<StackFrame>, Vec<Variable>), Box<dyn std::error::Error>> {
+        let level = self.selected_frame;
+        let stack = self.stack_frames()?;
+        let frame = stack.get(level).ok_or("The selected frame no longer exists")?;
+        let inline_depth =
+            stack[..level].iter().filter(|other| other.real_level == frame.real_level).count();
+        let base = self.get_base_address()?;
+        // A caller's PC is the return address, which can be past the end of the scope
+        let pc = if frame.real_level == 0 { frame.frame.pc } else { frame.frame.pc - 1 };
+        let variables = find_variables_at_pc(&self.dwinfo.dwarf, pc, base, inline_depth)?;
+        Ok((stack, variables))
+    }
+
+    /// The variables in scope in the selected frame, innermost scope first, each
+    /// with its value or why it couldn't be read.
+    pub fn frame_variables(
+        &mut self,
+    ) -> Result<Vec<(Variable, VariableValue)>, Box<dyn std::error::Error>> {
+        let (stack, variables) = self.frame_scope()?;
+        let base = self.get_base_address()?;
+        Ok(variables
+            .into_iter()
+            .map(|variable| {
+                let value = self.read_variable(&stack, base, &variable);
+                (variable, value)
+            })
+            .collect())
+    }
+
+    /// The value of the variable called `name` in the selected frame, if there is one
+    /// in scope.
+    pub fn variable_value(
+        &mut self,
+        name: &str,
+    ) -> Result<Option<TypedValue>, Box<dyn std::error::Error>> {
+        let (stack, variables) = self.frame_scope()?;
+        let base = self.get_base_address()?;
+        match variables.iter().find(|variable| variable.name == name) {
+            Some(variable) => Ok(Some(self.read_variable(&stack, base, variable)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Reads `variable` of the selected frame of `stack`.
+    fn read_variable(&self, stack: &[StackFrame], base: u64, variable: &Variable) -> VariableValue {
+        let size = variable.ty.size as usize;
+        let bytes = match &variable.location {
+            VariableLocation::Expression(expression) => {
+                let level = self.selected_frame;
+                let unit = variable.unit;
+                let mut context = FrameContext { target: self, stack, level, base, unit };
+                let frame_base = variable.frame_base;
+                read_location(*expression, variable.encoding, size, frame_base, &mut context)?
+            }
+            VariableLocation::Constant(bytes) => {
+                let mut bytes = bytes.clone();
+                bytes.resize(size, 0);
+                bytes
+            }
+            VariableLocation::OptimizedOut => return Err(OPTIMIZED_OUT.to_string()),
+        };
+        Ok(TypedValue { ty: variable.ty.clone(), bytes })
+    }
+
+    /// Every file mapped into the process, with the address it is loaded at and the
+    /// end of its last mapping, read from `/proc/[pid]/maps`.
+    fn mapped_files(&self) -> std::io::Result<BTreeMap<String, (u64, u64)>> {
+        let file = std::fs::File::open(format!("/proc/{}/maps", self.pid.0))?;
+        let mut files: BTreeMap<String, (u64, u64)> = BTreeMap::new();
+        for line in std::io::BufReader::new(file).lines() {
+            // start-end perms offset dev inode path
+            let line = line?;
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [range, _, _, _, _, path] = fields[..] else {
+                continue;
+            };
+            if !path.starts_with('/') {
+                continue;
+            }
+            let Some((start, end)) = range.split_once('-') else {
+                continue;
+            };
+            let (Ok(start), Ok(end)) = (u64::from_str_radix(start, 16), u64::from_str_radix(end, 16))
+            else {
+                continue;
+            };
+            let entry = files.entry(path.to_string()).or_insert((start, end));
+            entry.0 = entry.0.min(start);
+            entry.1 = entry.1.max(end);
+        }
+        Ok(files)
+    }
+
+    /// The source location of the current PC, if there is debug info for it.
+    pub fn current_code_point(&mut self) -> Result<Option<CodePoint>, Box<dyn std::error::Error>> {
+        self.ensure_running()?;
+        let pc = ptrace::get_reg(self.tid(), Register::pc())?;
+        let base = self.get_base_address()?;
+        Ok(find_function_at_pc(&self.dwinfo.dwarf, pc, base)?)
+    }
+
     pub fn read_word(&mut self, addr: u64) -> Result<i64, ptrace::Error> {
         ptrace::peekdata(self.pid, addr)
     }
@@ -99,23 +1706,163 @@ impl Target {
         ptrace::pokedata(self.pid, addr, data)
     }
 
-    fn wait_signal(&self) {
-        wait_for_signal(self.pid);
+    /// Waits for a thread to stop and decodes why it did. That thread becomes the
+    /// current one and every other thread is stopped as well. Stops that are only
+    /// thread bookkeeping, like a new thread starting, are handled here.
+    fn wait_signal(&mut self) -> Result<StopReason, Box<dyn std::error::Error>> {
+        loop {
+            let Some((tid, status)) = wait_for_any_signal() else {
+                self.exited = true;
+                return Ok(StopReason::Killed(libc::SIGKILL));
+            };
+            let Some(number) = self.thread_number(tid) else {
+                // A new thread or process can report its first stop before its
+                // creator's clone or fork event
+                if std::path::Path::new(&format!("/proc/{}/task/{}", self.pid.0, tid.0)).exists() {
+                    let number = self.add_thread(tid)?;
+                    self.resume_thread(number)?;
+                } else {
+                    self.stopped_new_processes.insert(tid);
+                }
+                continue;
+            };
+
+            if libc::WIFEXITED(status) || libc::WIFSIGNALED(status) {
+                if tid == self.pid {
+                    self.exited = true;
+                    return Ok(StopReason::from_wait_status(tid, status)?);
+                }
+                self.remove_thread(number);
+                if self.stepping_thread == Some(number) {
+                    // The others are all stopped already
+                    self.stepping_thread = None;
+                    return Ok(StopReason::SingleStep);
+                }
+                continue;
+            }
+
+            let thread = self.threads.get_mut(&number).expect("Thread exists");
+            thread.running = false;
+            let signo = libc::WSTOPSIG(status);
+            let event = status >> 16;
+            if signo == libc::SIGSTOP && event == 0 && thread.expecting_sigstop {
+                thread.expecting_sigstop = false;
+                self.resume_thread(number)?;
+                continue;
+            }
+            if event == libc::PTRACE_EVENT_CLONE {
+                let new_thread = self.track_cloned_thread(tid)?;
+                self.resume_thread(new_thread)?;
+                self.resume_thread(number)?;
+                continue;
+            }
+            if event == libc::PTRACE_EVENT_FORK || event == libc::PTRACE_EVENT_VFORK {
+                let followed = self.follow_fork(number, event == libc::PTRACE_EVENT_VFORK)?;
+                self.resume_thread(followed)?;
+                continue;
+            }
+            if event == libc::PTRACE_EVENT_VFORK_DONE {
+                // The child has let go of our memory, so the `INT3`s can go back in
+                for site in self.breakpoints.values_mut() {
+                    site.enable()?;
+                }
+                self.resume_thread(number)?;
+                continue;
+            }
+            if event == libc::PTRACE_EVENT_EXEC {
+                let program = self.reload_after_exec(tid)?;
+                return Ok(StopReason::Exec(program));
+            }
+
+            let reason = StopReason::from_wait_status(tid, status)?;
+            self.current_thread = number;
+            self.stop_other_threads()?;
+            return Ok(reason);
+        }
+    }
+
+    /// Fails if the process has exited, rather than letting ptrace fail obscurely.
+    pub fn ensure_running(&self) -> Result<(), &'static str> {
+        match self.exited {
+            true => Err("The program is not being run."),
+            false => Ok(()),
+        }
     }
 
     pub fn pid(&self) -> Pid {
         self.pid
     }
 
-    pub fn kill(&mut self) -> Result<(), &'static str> {
-        unsafe {
-            // TODO: Error check here, this is fallible
-            match libc::kill(self.pid.0, libc::SIGKILL) {
-                -1 => Err("Could not kill process"),
-                0 => Ok(()),
-                _ => unreachable!("libc should only return 0 or -1 for lib::kill"),
+    /// Kills the process and waits until it is gone.
+    pub fn kill(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.ensure_running()?;
+        if unsafe { libc::kill(self.pid.0, libc::SIGKILL) } == -1 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+
+        // Reap every thread so none lingers as a zombie, skipping stops reported
+        // before they died. The main thread is reported last.
+        while let Some((tid, status)) = wait_for_any_signal() {
+            if tid == self.pid && (libc::WIFEXITED(status) || libc::WIFSIGNALED(status)) {
+                break;
+            }
+        }
+        self.exited = true;
+        Ok(())
+    }
+
+    /// Removes every `INT3` and debug register we put into the process and stops
+    /// tracing it, leaving it running as if we were never there.
+    pub fn detach(&mut self) -> Result<(), ptrace::Error> {
+        for site in self.breakpoints.values_mut() {
+            if site.enabled() {
+                site.disable()?;
+            }
+        }
+        self.breakpoints.clear();
+
+        let tids = self.thread_ids();
+        for slot in 0..debugreg::NUM_SLOTS {
+            if self.debug_register_slots[slot].take().is_some() {
+                for &tid in &tids {
+                    debugreg::clear_slot(tid, slot)?;
+                }
+            }
+        }
+
+        let numbers: Vec<usize> = self.threads.keys().copied().collect();
+        for number in numbers {
+            if !self.take_expected_sigstop(number)? {
+                self.remove_thread(number);
+            }
+        }
+        for thread in self.threads.values() {
+            ptrace::detach(thread.tid, thread.pending_signal.unwrap_or(0))?;
+        }
+        Ok(())
+    }
+
+    /// Lets thread `number` take the `SIGSTOP` we sent it while it was stopping for
+    /// something else, which would otherwise leave it stopped once we detach. Signals
+    /// it receives first are kept to be passed on. Returns whether the thread is still
+    /// there.
+    fn take_expected_sigstop(&mut self, number: usize) -> Result<bool, ptrace::Error> {
+        let thread = self.threads.get_mut(&number).expect("Thread exists");
+        while thread.expecting_sigstop {
+            ptrace::cont(thread.tid, thread.pending_signal.take().unwrap_or(0))?;
+            let status = wait_for_signal(thread.tid);
+            if !libc::WIFSTOPPED(status) {
+                return Ok(false);
+            }
+            let signo = libc::WSTOPSIG(status);
+            let event = status >> 16;
+            if signo == libc::SIGSTOP && event == 0 {
+                thread.expecting_sigstop = false;
+            } else if event == 0 && signo != libc::SIGTRAP && self.signals.get(signo).pass {
+                thread.pending_signal = Some(signo);
             }
         }
+        Ok(true)
     }
 
     pub fn get_base_address(&mut self) -> std::io::Result<u64> {
@@ -155,7 +1902,11 @@ impl Target {
 
 impl Debug for Target {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Target {{ pid: {:?}, breakpoints: {:?} }}, ", self.pid, self.breakpoints)?;
+        write!(
+            f,
+            "Target {{ pid: {:?}, breakpoints: {:?}, user_breakpoints: {:?} }}, ",
+            self.pid, self.breakpoints, self.user_breakpoints
+        )?;
         match self.base_address {
             None => write!(f, "Base address: None")?,
             Some(addr) => write!(f, "Base address: {:x?}", addr)?
@@ -164,3 +1915,89 @@ impl Debug for Target {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static mut WATCHED: u64 = 0;
+
+    /// Forks a copy of the test process that stops itself under ptrace and then runs
+    /// `child`, which shares our addresses.
+    fn traced_child(child: fn()) -> Target {
+        match fork::fork().expect("Could not fork") {
+            fork::Fork::Parent(pid) => {
+                let pid = Pid(pid);
+                wait_for_signal(pid);
+                let dwinfo = DwarfInfo::load("/proc/self/exe").expect("Could not read our own debug info");
+                Target::new(pid, dwinfo, LaunchSettings::new(String::new(), Vec::new()))
+            }
+            fork::Fork::Child => {
+                if ptrace::trace_me().is_ok() {
+                    unsafe { libc::raise(libc::SIGSTOP) };
+                    child();
+                }
+                unsafe { libc::_exit(0) }
+            }
+        }
+    }
+
+    #[inline(never)]
+    fn conditional_countdown(n: u64) -> u64 {
+        match n {
+            0 => 0,
+            n => std::hint::black_box(conditional_countdown(n - 1)) + 1,
+        }
+    }
+
+    fn watched_value(reason: StopReason) -> (u64, u64) {
+        let StopReason::Watchpoint { old, new, .. } = reason else {
+            panic!("Expected a watchpoint stop, got {reason:?}");
+        };
+        let word = |value: TypedValue| u64::from_le_bytes(value.bytes.try_into().unwrap());
+        (word(old), word(new))
+    }
+
+    #[test]
+    fn stepping_over_a_watched_write_stops() {
+        let mut target = traced_child(|| unsafe {
+            std::ptr::write_volatile(std::ptr::addr_of_mut!(WATCHED), 1);
+            std::ptr::write_volatile(std::ptr::addr_of_mut!(WATCHED), 3);
+        });
+        let addr = std::ptr::addr_of!(WATCHED) as u64;
+        let ty = TypeInfo::primitive("u64").unwrap();
+        target.add_watchpoint(addr, ty, WatchAccess::Write).unwrap();
+
+        let mut reason = target.step_instruction().unwrap();
+        for _ in 0..10_000 {
+            if !matches!(reason, StopReason::SingleStep) {
+                break;
+            }
+            reason = target.step_instruction().unwrap();
+        }
+        assert_eq!(watched_value(reason), (0, 1));
+
+        // The hit during the step moved the old value on, so continuing reports the
+        // next write from 1 rather than from 0
+        assert_eq!(watched_value(target.continue_process().unwrap()), (1, 3));
+        assert!(matches!(target.continue_process().unwrap(), StopReason::Exited(0)));
+    }
+
+    #[test]
+    fn function_breakpoint_condition_sees_arguments() {
+        let mut target = traced_child(|| {
+            conditional_countdown(std::hint::black_box(3));
+        });
+        let location = Location::Function("conditional_countdown".to_string());
+        let number = target.add_breakpoint(location, BreakpointKind::Software).unwrap();
+        target.set_breakpoint_condition(number, Some("n == 1".parse().unwrap())).unwrap();
+
+        let reason = target.continue_process().unwrap();
+        assert!(matches!(&reason, StopReason::Breakpoint(numbers) if *numbers == [number]), "{reason:?}");
+        let n = target.variable_value("n").unwrap().expect("n is in scope");
+        assert_eq!(n.to_i64(), 1);
+
+        assert!(matches!(target.continue_process().unwrap(), StopReason::Exited(0)));
+        assert_eq!(target.user_breakpoints[&number].hit_count, 1);
+    }
+}
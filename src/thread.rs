@@ -0,0 +1,61 @@
+use crate::prelude::*;
+use crate::stop::SignalInfo;
+
+/// A thread of the process being debugged.
+#[derive(Debug, Clone)]
+pub struct Thread {
+    /// Our own small number for the thread, as used by `thread N`. The main thread is 1.
+    pub number: usize,
+    pub tid: Pid,
+    /// Whether the thread is running, as opposed to stopped under our control.
+    pub running: bool,
+    /// The signal to deliver to the thread the next time it is resumed.
+    pub pending_signal: Option<i32>,
+    /// Whether we sent the thread a `SIGSTOP` it stopped for something else before
+    /// receiving. It will report that `SIGSTOP` once resumed, which must be ignored.
+    pub expecting_sigstop: bool,
+    /// Whether the thread trapped on a breakpoint that was never reported, because
+    /// another thread stopped first. It was moved back to hit it again when resumed.
+    pub rewound_trap: bool,
+    /// Whether the thread's last system call stop was on entry, so the next one is
+    /// on exit.
+    pub in_syscall: bool,
+    /// A signal set to stop that the thread received while another thread's stop was
+    /// being reported. It is reported instead of resuming the next time.
+    pub held_signal: Option<SignalInfo>,
+}
+
+impl Thread {
+    pub fn new(number: usize, tid: Pid) -> Self {
+        Self {
+            number,
+            tid,
+            running: false,
+            pending_signal: None,
+            expecting_sigstop: false,
+            rewound_trap: false,
+            in_syscall: false,
+            held_signal: None,
+        }
+    }
+}
+
+/// Sends `signal` to just the thread `tid` of process `pid`.
+pub fn tgkill(pid: Pid, tid: Pid, signal: i32) -> std::io::Result<()> {
+    match unsafe { libc::tgkill(pid.0, tid.0, signal) } {
+        -1 => Err(std::io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+/// The ids of every thread of process `pid`, read from `/proc/[pid]/task`.
+pub fn list_threads(pid: Pid) -> std::io::Result<Vec<Pid>> {
+    let mut tids = Vec::new();
+    for entry in std::fs::read_dir(format!("/proc/{}/task", pid.0))? {
+        if let Ok(tid) = entry?.file_name().to_string_lossy().parse() {
+            tids.push(Pid(tid));
+        }
+    }
+    tids.sort();
+    Ok(tids)
+}